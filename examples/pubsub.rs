@@ -2,11 +2,11 @@ extern crate nmsg;
 
 use std::borrow::Cow;
 use std::mem;
-use std::slice;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::thread;
 
 use nmsg::*;
+use nmsg::pod::BigEndian;
 
 const TIME: &'static str = "TM:";
 const COUNT: &'static str = "CT:";
@@ -16,21 +16,10 @@ fn epoch() -> u64 {
     now.duration_since(UNIX_EPOCH).unwrap().as_secs()
 }
 
-fn write_u64(out: &mut [u8], v: u64) {
-    let bytes: &[u8] = unsafe {
-        slice::from_raw_parts(&v as *const _ as *const u8, mem::size_of::<u64>())
-    };
-    out.copy_from_slice(bytes);
-}
-
-fn read_msg(msg: &[u8]) -> (Cow<str>, u64) {
+fn read_msg(msg: &MessageBuffer) -> (Cow<str>, u64) {
     let split = msg.iter().position(|&x| x == b':').unwrap_or(0);
     let prefix = String::from_utf8_lossy(&msg[0..split]);
-    let val = if msg.len() >= split + 1 + mem::size_of::<u64>() {
-        unsafe { *(&msg[split+1] as *const _ as *const u64) }
-    } else {
-        0
-    };
+    let val = msg.read_pod::<BigEndian<u64>>(split + 1).map(BigEndian::get).unwrap_or(0);
     (prefix, val)
 }
 
@@ -38,7 +27,7 @@ fn publish(sock: &Pub, topic: &str, val: u64) -> Result<()> {
     let offset = topic.len();
     let mut msg = MessageBuffer::new(offset + mem::size_of::<u64>());
     msg[0..offset].copy_from_slice(topic.as_bytes());
-    write_u64(&mut msg[offset..], val);
+    msg.write_pod(offset, &BigEndian::new(val))?;
     sock.send(msg)?;
     Ok(())
 }
@@ -0,0 +1,322 @@
+//! Procedural macros for [nmsg](https://docs.rs/nmsg).
+//!
+//! These are re-exported by nmsg with the `derive` feature, and shouldn't be used directly.
+extern crate proc_macro;
+extern crate proc_macro2;
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote, ToTokens};
+use syn::{FnArg, GenericArgument, Ident, ItemTrait, Pat, PathArguments, ReturnType, TraitItem, Type};
+
+/// A method of the service trait.
+struct Method {
+    name: Ident,
+    variant: Ident,
+    args: Vec<(Ident, Type)>,
+    /// The success type, and the error type if the method returns a `Result`.
+    output: Type,
+    error: Option<Type>,
+    /// The declared return type.
+    ret: ReturnType
+}
+
+/// Turn `snake_case` into `CamelCase`.
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new()
+            }
+        })
+        .collect()
+}
+
+/// Split `Result<T, E>` into `T` and `E`.
+fn split_result(ty: &Type) -> Option<(Type, Type)> {
+    let path = match *ty {
+        Type::Path(ref path) if path.qself.is_none() => &path.path,
+        _ => return None
+    };
+    let last = path.segments.last()?;
+    if last.ident != "Result" {
+        return None;
+    }
+    let args = match last.arguments {
+        PathArguments::AngleBracketed(ref args) if args.args.len() == 2 => &args.args,
+        _ => return None
+    };
+    match (&args[0], &args[1]) {
+        (GenericArgument::Type(ok), GenericArgument::Type(err)) => Some((ok.clone(), err.clone())),
+        _ => None
+    }
+}
+
+fn parse_method(item: &TraitItem) -> syn::Result<Method> {
+    let method = match *item {
+        TraitItem::Fn(ref method) => method,
+        ref other => return Err(syn::Error::new_spanned(other, "services can only contain methods"))
+    };
+    let sig = &method.sig;
+    if !sig.generics.params.is_empty() || sig.asyncness.is_some() {
+        return Err(syn::Error::new_spanned(sig, "service methods can't be generic or async"));
+    }
+    let mut inputs = sig.inputs.iter();
+    match inputs.next() {
+        Some(FnArg::Receiver(recv)) if recv.reference.is_some() && recv.mutability.is_none() => {},
+        _ => return Err(syn::Error::new_spanned(sig, "service methods must take `&self`"))
+    }
+    let mut args = Vec::new();
+    for (i, input) in inputs.enumerate() {
+        let arg = match *input {
+            FnArg::Typed(ref arg) => arg,
+            FnArg::Receiver(_) => unreachable!()
+        };
+        let name = match *arg.pat {
+            Pat::Ident(ref pat) => pat.ident.clone(),
+            _ => format_ident!("arg{}", i)
+        };
+        args.push((name, (*arg.ty).clone()));
+    }
+    let (output, error) = match sig.output {
+        ReturnType::Default => (syn::parse_quote!(()), None),
+        ReturnType::Type(_, ref ty) => match split_result(ty) {
+            Some((ok, err)) => (ok, Some(err)),
+            None => ((**ty).clone(), None)
+        }
+    };
+    Ok(Method {
+        name: sig.ident.clone(),
+        variant: Ident::new(&camel_case(&sig.ident.to_string()), sig.ident.span()),
+        args,
+        output,
+        error,
+        ret: sig.output.clone()
+    })
+}
+
+/// A 64-bit FNV-1a hash of the service signature.
+fn version(name: &Ident, methods: &[Method]) -> u64 {
+    let mut signature = name.to_string();
+    for method in methods {
+        signature.push_str(&format!(";{}(", method.name));
+        for (_, ty) in &method.args {
+            signature.push_str(&ty.to_token_stream().to_string());
+            signature.push(',');
+        }
+        signature.push_str(&format!(")->{}", method.ret.to_token_stream()));
+    }
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for b in signature.bytes() {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn expand(item: ItemTrait) -> syn::Result<proc_macro2::TokenStream> {
+    let methods = item.items.iter().map(parse_method).collect::<syn::Result<Vec<_>>>()?;
+    let vis = &item.vis;
+    let name = &item.ident;
+    let method_enum = format_ident!("{}Method", name);
+    let client = format_ident!("{}Client", name);
+    let dispatcher = format_ident!("{}Dispatcher", name);
+    let version = version(name, &methods);
+
+    let variants = methods.iter().map(|m| &m.variant).collect::<Vec<_>>();
+    let names = methods.iter().map(|m| m.name.to_string()).collect::<Vec<_>>();
+
+    let client_methods = methods.iter().map(|m| {
+        let method = &m.name;
+        let method_name = method.to_string();
+        let arg_names = m.args.iter().map(|a| &a.0).collect::<Vec<_>>();
+        let arg_types = m.args.iter().map(|a| &a.1).collect::<Vec<_>>();
+        let output = &m.output;
+        let doc = format!("Call `{}` on the server.", method_name);
+        quote! {
+            #[doc = #doc]
+            pub fn #method(&mut self, #(#arg_names: #arg_types),*) -> ::nmsg::rpc::Result<#output> {
+                self.inner.call::<_, #output>(#method_name, &(#(#arg_names,)*))
+            }
+        }
+    });
+
+    let dispatch_arms = methods.iter().map(|m| {
+        let method = &m.name;
+        let method_name = method.to_string();
+        let arg_names = m.args.iter().map(|a| &a.0).collect::<Vec<_>>();
+        let arg_types = m.args.iter().map(|a| &a.1).collect::<Vec<_>>();
+        let call = quote! { self.service.#method(#(#arg_names),*) };
+        let result = match m.error {
+            Some(_) => quote! { #call.map_err(::std::convert::Into::<::nmsg::rpc::RemoteError>::into)? },
+            None => call
+        };
+        quote! {
+            #method_name => {
+                let (#(#arg_names,)*): (#(#arg_types,)*) = ::nmsg::codec::Codec::decode(&self.codec, args)
+                    .map_err(|e| ::nmsg::rpc::RemoteError::new(::nmsg::rpc::ErrorCode::BadRequest, e.to_string()))?;
+                let result = #result;
+                ::nmsg::codec::Codec::encode(&self.codec, &result)
+                    .map_err(|e| ::nmsg::rpc::RemoteError::new(::nmsg::rpc::ErrorCode::Internal, e.to_string()))
+            }
+        }
+    });
+
+    let method_enum_doc = format!("The methods of [`{}`](trait.{}.html).", name, name);
+    let client_doc = format!("A client for the [`{}`](trait.{}.html) service.", name, name);
+    let dispatcher_doc = format!(
+        "Dispatches calls to an implementation of [`{}`](trait.{}.html).\n\n\
+         Use [`handle`](#method.handle) with `Rep::reply` or `Rep::reply_loop`.", name, name);
+
+    Ok(quote! {
+        #item
+
+        #[doc = #method_enum_doc]
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        #vis enum #method_enum {
+            #(#[allow(missing_docs)] #variants),*
+        }
+
+        impl #method_enum {
+            /// A hash of the service signature.
+            ///
+            /// Clients and servers with different versions reject each other's calls with
+            /// `ErrorCode::VersionMismatch`.
+            pub const SERVICE_VERSION: u64 = #version;
+
+            /// All the methods.
+            pub const ALL: &'static [#method_enum] = &[#(#method_enum::#variants),*];
+
+            /// The name of the method on the wire.
+            pub fn name(self) -> &'static str {
+                match self {
+                    #(#method_enum::#variants => #names),*
+                }
+            }
+
+            /// Look up a method by its name on the wire.
+            pub fn from_name(name: &str) -> ::std::option::Option<#method_enum> {
+                match name {
+                    #(#names => ::std::option::Option::Some(#method_enum::#variants),)*
+                    _ => ::std::option::Option::None
+                }
+            }
+        }
+
+        #[doc = #client_doc]
+        #vis struct #client<C> {
+            inner: ::nmsg::rpc::Client<C>
+        }
+
+        impl<C: ::nmsg::codec::Codec> #client<C> {
+            /// Create a client that calls the service over `sock`.
+            pub fn new(sock: ::nmsg::Req, codec: C) -> #client<C> {
+                let mut inner = ::nmsg::rpc::Client::new(sock, codec);
+                inner.set_service_version(#method_enum::SERVICE_VERSION);
+                #client { inner }
+            }
+
+            /// Get a reference to the socket, for example to connect it.
+            pub fn get_ref(&self) -> &::nmsg::Req {
+                self.inner.get_ref()
+            }
+
+            /// Set how long calls wait for a reply.
+            pub fn set_timeout(&mut self, timeout: ::std::option::Option<::std::time::Duration>) {
+                self.inner.set_timeout(timeout)
+            }
+
+            #(#client_methods)*
+        }
+
+        #[doc = #dispatcher_doc]
+        #vis struct #dispatcher<T, C> {
+            service: T,
+            codec: C
+        }
+
+        impl<T: #name, C: ::nmsg::codec::Codec> #dispatcher<T, C> {
+            /// Create a dispatcher for `service`.
+            pub fn new(service: T, codec: C) -> #dispatcher<T, C> {
+                #dispatcher { service, codec }
+            }
+
+            /// Get a reference to the service.
+            pub fn service(&self) -> &T {
+                &self.service
+            }
+
+            /// Handle a request, and return the reply.
+            ///
+            /// Errors are reported to the client in the reply, so this never fails. The
+            /// `Result` is for use with `Rep::reply_loop`.
+            pub fn handle(&self, request: ::nmsg::MessageBuffer)
+                -> ::std::result::Result<::nmsg::MessageBuffer, ::nmsg::Error>
+            {
+                ::std::result::Result::Ok(self.dispatch(&request))
+            }
+
+            /// Handle a request, and return the reply.
+            pub fn dispatch(&self, request: &[u8]) -> ::nmsg::MessageBuffer {
+                let call = match ::nmsg::rpc::Call::decode(request) {
+                    ::std::result::Result::Ok(call) => call,
+                    ::std::result::Result::Err(_) => {
+                        let err = ::nmsg::rpc::RemoteError::new(::nmsg::rpc::ErrorCode::BadRequest, "malformed envelope");
+                        return ::nmsg::rpc::encode_reply(0, ::std::result::Result::Err(&err));
+                    }
+                };
+                match self.call(&call) {
+                    ::std::result::Result::Ok(body) => ::nmsg::rpc::encode_reply(call.id, ::std::result::Result::Ok(&body)),
+                    ::std::result::Result::Err(err) => ::nmsg::rpc::encode_reply(call.id, ::std::result::Result::Err(&err))
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn call(&self, call: &::nmsg::rpc::Call)
+                -> ::std::result::Result<::nmsg::MessageBuffer, ::nmsg::rpc::RemoteError>
+            {
+                call.check(#method_enum::SERVICE_VERSION)?;
+                let args = call.args;
+                match call.method {
+                    #(#dispatch_arms)*
+                    method => ::std::result::Result::Err(::nmsg::rpc::RemoteError::new(
+                        ::nmsg::rpc::ErrorCode::UnknownMethod,
+                        ::std::format!("unknown method {}", method)))
+                }
+            }
+        }
+    })
+}
+
+/// Generate an RPC client and dispatcher from a trait.
+///
+/// For a trait `Name`, this generates:
+///
+/// * `NameMethod`: an enum of the methods, along with the service version.
+/// * `NameClient<C>`: a client over a `Req` socket, with a method for each trait method.
+/// * `NameDispatcher<T, C>`: dispatches requests to an implementation of `Name`.
+///
+/// Every method must take `&self`. Arguments must implement `Serialize` and `DeserializeOwned`,
+/// and so must return types. A method returning `Result<T, E>` reports `E` to the client as an
+/// error, which requires `E: Into<nmsg::rpc::RemoteError>`.
+#[proc_macro_attribute]
+pub fn service(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(Span::call_site(), "#[service] doesn't take arguments")
+            .to_compile_error()
+            .into();
+    }
+    let item = match syn::parse::<ItemTrait>(item) {
+        Ok(item) => item,
+        Err(e) => return e.to_compile_error().into()
+    };
+    match expand(item) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into()
+    }
+}
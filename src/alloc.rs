@@ -1,5 +1,7 @@
 //! Module for allocating zero-copy buffers for nanomsg.
 use std::borrow::{Borrow, BorrowMut};
+use std::cmp;
+use std::io;
 use std::mem;
 use std::ops::{Deref, DerefMut, Index, IndexMut, Range, RangeFull, RangeTo, RangeFrom};
 use std::slice;
@@ -7,6 +9,9 @@ use std::slice;
 use nanomsg_sys::{nn_allocmsg, nn_freemsg, nn_reallocmsg};
 use libc::{c_void, memset};
 
+use error::Result;
+use pod::{self, Pod};
+
 /// A buffer of data for zero-copy messages with nanomsg
 ///
 /// This is a buffer of bytes that avoids being copied when sent or received with
@@ -14,10 +19,18 @@ use libc::{c_void, memset};
 ///
 /// The `MessageBuffer` implements `Drop` so that it will automatically
 /// free its memory when it goes out of scope.
+///
+/// # Thread Safety
+///
+/// `MessageBuffer` is `Send` and `Sync`. A message received on one thread can be handed to
+/// another thread, which can read it, send it, or drop it, without copying it. nanomsg allocates
+/// message chunks from a thread safe allocator, and the buffer is the only owner of its chunk.
 #[derive(Debug)]
 pub struct MessageBuffer {
     ptr: *mut c_void,
-    size: usize
+    size: usize,
+    /// The size the chunk was allocated with, which shrinking doesn't reduce.
+    capacity: usize
 }
 
 impl MessageBuffer {
@@ -33,7 +46,8 @@ impl MessageBuffer {
 
         MessageBuffer {
             ptr,
-            size
+            size,
+            capacity: size
         }
     }
 
@@ -47,7 +61,8 @@ impl MessageBuffer {
         };
         MessageBuffer {
             ptr,
-            size
+            size,
+            capacity: size
         }
     }
 
@@ -61,6 +76,7 @@ impl MessageBuffer {
         }
         self.ptr = ptr;
         self.size = new_size;
+        self.capacity = cmp::max(self.capacity, new_size);
     }
 
     /// The length of the `MessageBuffer` in bytes.
@@ -85,6 +101,58 @@ impl MessageBuffer {
         self
     }
 
+    /// Create a new `MessageBuffer` containing the bytes of a plain-old-data value.
+    ///
+    /// # See Also
+    ///
+    /// * [`pod`](../pod/index.html)
+    pub fn from_pod<T: Pod>(value: &T) -> MessageBuffer {
+        MessageBuffer::from(pod::bytes_of(value))
+    }
+
+    /// Read a plain-old-data value starting at `offset`.
+    ///
+    /// The value doesn't need to be aligned within the buffer.
+    ///
+    /// # Returns
+    ///
+    /// The value, or `Err(error::INVALID)` if the buffer is too short.
+    #[inline]
+    pub fn read_pod<T: Pod>(&self, offset: usize) -> Result<T> {
+        pod::read(self, offset)
+    }
+
+    /// Write a plain-old-data value starting at `offset`.
+    ///
+    /// The value doesn't need to be aligned within the buffer.
+    ///
+    /// # Returns
+    ///
+    /// `Err(error::INVALID)` if the buffer is too short.
+    #[inline]
+    pub fn write_pod<T: Pod>(&mut self, offset: usize, value: &T) -> Result<()> {
+        pod::write(self, offset, value)
+    }
+
+    /// View the entire buffer as a slice of plain-old-data values.
+    ///
+    /// # Returns
+    ///
+    /// The slice, or `Err(error::INVALID)` if the buffer isn't aligned for `T` or its length isn't
+    /// a multiple of the size of `T`.
+    #[inline]
+    pub fn as_pod_slice<T: Pod>(&self) -> Result<&[T]> {
+        pod::cast_slice(self)
+    }
+
+    /// View the entire buffer as a mutable slice of plain-old-data values.
+    ///
+    /// See [`as_pod_slice`](#method.as_pod_slice).
+    #[inline]
+    pub fn as_pod_slice_mut<T: Pod>(&mut self) -> Result<&mut [T]> {
+        pod::cast_slice_mut(self)
+    }
+
     /// Convert the buffer to a raw pointer.
     ///
     /// It is the user's responsibility to free the buffer
@@ -103,7 +171,8 @@ impl MessageBuffer {
         assert!(!ptr.is_null());
         MessageBuffer {
             ptr,
-            size
+            size,
+            capacity: size
         }
     }
 }
@@ -117,6 +186,12 @@ impl Drop for MessageBuffer {
     }
 }
 
+// The buffer uniquely owns its nanomsg chunk, and nn_allocmsg, nn_reallocmsg and nn_freemsg may
+// be called from any thread, so the buffer can be moved to another thread.
+unsafe impl Send for MessageBuffer {}
+// A shared reference only gives read access to the bytes, and there is no interior mutability.
+unsafe impl Sync for MessageBuffer {}
+
 impl Deref for MessageBuffer {
     type Target = [u8];
 
@@ -229,3 +304,217 @@ impl IndexMut<RangeFull> for MessageBuffer {
     }
 }
 
+
+/// A writer that writes directly into a growable `MessageBuffer`.
+///
+/// This can be used to serialize data into a message without an intermediate buffer. The
+/// underlying buffer is grown as needed, and trimmed to the number of bytes written by
+/// [`into_buffer`](#method.into_buffer).
+#[derive(Debug)]
+pub struct MessageWriter {
+    buffer: MessageBuffer,
+    len: usize
+}
+
+impl MessageWriter {
+    /// Create a new `MessageWriter` with a default initial capacity.
+    pub fn new() -> MessageWriter {
+        MessageWriter::with_capacity(64)
+    }
+
+    /// Create a new `MessageWriter` that can hold `capacity` bytes before it needs to grow.
+    pub fn with_capacity(capacity: usize) -> MessageWriter {
+        MessageWriter {
+            buffer: MessageBuffer::new(cmp::max(capacity, 1)),
+            len: 0
+        }
+    }
+
+    /// The number of bytes written so far.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return true if nothing has been written yet.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Convert the writer into a `MessageBuffer` containing the bytes written.
+    ///
+    /// Shrinking a nanomsg message doesn't reallocate, so this doesn't copy the data.
+    pub fn into_buffer(mut self) -> MessageBuffer {
+        let len = self.len;
+        self.buffer.resize(len);
+        self.buffer
+    }
+}
+
+impl Default for MessageWriter {
+    fn default() -> MessageWriter {
+        MessageWriter::new()
+    }
+}
+
+impl io::Write for MessageWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let end = self.len + data.len();
+        if end > self.buffer.len() {
+            let new_size = cmp::max(end, self.buffer.len() * 2);
+            self.buffer.resize(new_size);
+        }
+        self.buffer[self.len..end].copy_from_slice(data);
+        self.len = end;
+        Ok(data.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A pool of reusable `MessageBuffer`s.
+///
+/// Allocating and freeing a message for every send or receive can be costly when messages are
+/// exchanged at a high rate. The pool keeps buffers that are no longer needed (for example
+/// buffers returned by [`Socket::recv`](../socket/struct.Socket.html#method.recv)) and hands them
+/// out again instead of allocating a new one.
+///
+/// Buffers are grouped into size classes. A buffer is retained in the largest class that its
+/// allocation can fill, whatever its current length, and is handed out for any request that is
+/// no larger than its class. Shrinking a nanomsg message never reallocates, and the allocator
+/// can usually grow it back within its allocation in place, so reusing a buffer for a smaller
+/// message is cheap. Buffers the pool allocates itself have the size of their class.
+///
+/// # Note
+///
+/// Like [`MessageBuffer::new`](struct.MessageBuffer.html#method.new), the contents of a buffer
+/// returned by the pool are uninitialized, and may contain data from a previous message.
+#[derive(Debug)]
+pub struct MessageBufferPool {
+    classes: Vec<SizeClass>,
+    max_retained: usize,
+    stats: PoolStats
+}
+
+#[derive(Debug)]
+struct SizeClass {
+    size: usize,
+    free: Vec<MessageBuffer>
+}
+
+/// Statistics for a [`MessageBufferPool`](struct.MessageBufferPool.html).
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct PoolStats {
+    /// Number of requests that were served from a retained buffer.
+    pub hits: u64,
+    /// Number of requests that had to allocate a new buffer.
+    pub misses: u64,
+    /// Number of buffers that were returned to, and retained by, the pool.
+    pub returned: u64,
+    /// Number of buffers that were returned to the pool, but freed instead of retained.
+    ///
+    /// This happens if the buffer doesn't fit any size class, or its class is already full.
+    pub discarded: u64
+}
+
+impl MessageBufferPool {
+    /// Create a new pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `sizes`: The size classes of the pool, in bytes. The order doesn't matter and
+    ///   duplicates are ignored.
+    /// * `max_retained`: The maximum number of buffers to keep for each size class.
+    pub fn new(sizes: &[usize], max_retained: usize) -> MessageBufferPool {
+        let mut sizes = sizes.to_vec();
+        sizes.sort();
+        sizes.dedup();
+        MessageBufferPool {
+            classes: sizes.into_iter().map(|size| SizeClass { size, free: Vec::new() }).collect(),
+            max_retained,
+            stats: PoolStats::default()
+        }
+    }
+
+    /// Get a buffer of exactly `size` bytes.
+    ///
+    /// If the pool has a retained buffer in the smallest size class that can hold `size` bytes
+    /// it is reused. Otherwise a new buffer is allocated with the size of that class, so that it
+    /// can be reused once it is returned, or with exactly `size` bytes if no class is large
+    /// enough.
+    pub fn get(&mut self, size: usize) -> MessageBuffer {
+        let (mut buffer, hit) = match self.classes.iter_mut().find(|class| class.size >= size) {
+            Some(class) => match class.free.pop() {
+                Some(buffer) => (buffer, true),
+                None => (MessageBuffer::new(class.size), false)
+            },
+            None => (MessageBuffer::new(size), false)
+        };
+        if hit {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+        }
+        if buffer.len() != size {
+            buffer.resize(size);
+        }
+        buffer
+    }
+
+    /// Return a buffer to the pool.
+    ///
+    /// The buffer is retained in the largest size class that is no larger than its allocation,
+    /// which may be larger than its current length. If the allocation is smaller than the
+    /// smallest class, larger than the largest class, or its class already holds
+    /// `max_retained` buffers, it is freed.
+    pub fn put(&mut self, buffer: MessageBuffer) {
+        let capacity = buffer.capacity;
+        let max_retained = self.max_retained;
+        let fits = self.classes.last().is_some_and(|class| capacity <= class.size);
+        let class = self.classes.iter_mut()
+            .rev()
+            .find(|class| class.size <= capacity)
+            .filter(|class| fits && class.free.len() < max_retained);
+        match class {
+            Some(class) => {
+                self.stats.returned += 1;
+                class.free.push(buffer);
+            },
+            None => self.stats.discarded += 1
+        }
+    }
+
+    /// Pre-allocate buffers, so that each size class holds `count` buffers.
+    ///
+    /// `count` is limited to the maximum number of retained buffers per class.
+    pub fn fill(&mut self, count: usize) {
+        let count = cmp::min(count, self.max_retained);
+        for class in &mut self.classes {
+            while class.free.len() < count {
+                class.free.push(MessageBuffer::new(class.size));
+            }
+        }
+    }
+
+    /// Free all retained buffers.
+    pub fn clear(&mut self) {
+        for class in &mut self.classes {
+            class.free.clear();
+        }
+    }
+
+    /// The total number of buffers currently retained by the pool.
+    pub fn retained(&self) -> usize {
+        self.classes.iter().map(|class| class.free.len()).sum()
+    }
+
+    /// Get the hit and miss statistics for the pool.
+    #[inline]
+    pub fn stats(&self) -> PoolStats {
+        self.stats
+    }
+}
@@ -0,0 +1,217 @@
+//! Bridges between sockets and `std::sync::mpsc` channels.
+//!
+//! [`spawn_receiver`](fn.spawn_receiver.html) moves a socket to a background thread, which
+//! forwards every message received on it to a channel. [`spawn_sender`](fn.spawn_sender.html)
+//! does the opposite, sending every message written to a channel on the socket.
+//!
+//! The bounded variants use a `sync_channel`. When the channel is full, the receiving bridge
+//! stops receiving, so that backpressure propagates to the peers through nanomsg's own buffers.
+//!
+//! Each bridge returns a [`BridgeHandle`](struct.BridgeHandle.html), which stops the thread and
+//! reports why it exited.
+use std::panic;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use alloc::MessageBuffer;
+use error::{Result, TERMINATING, WOULD_BLOCK};
+use poller::poll_until;
+use protocol::{SPRecv, SPSend};
+use socket::{Flags, Socket};
+
+/// How often the bridge threads check whether they have been stopped.
+const STOP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A handle to a bridge thread.
+///
+/// Dropping the handle stops the thread, without waiting for it to exit.
+#[derive(Debug)]
+pub struct BridgeHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<()>>>
+}
+
+impl BridgeHandle {
+    fn spawn<F>(name: &str, run: F) -> BridgeHandle
+        where F: FnOnce(&AtomicBool) -> Result<()> + Send + 'static
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || run(&thread_stop))
+            .expect("failed to spawn bridge thread");
+        BridgeHandle { stop, thread: Some(thread) }
+    }
+
+    /// Ask the bridge thread to stop.
+    ///
+    /// The thread notices within about 100ms. Messages that are in flight are dropped.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    /// Return true if the bridge thread has exited.
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(|t| t.is_finished())
+    }
+
+    /// Wait for the bridge thread to exit.
+    ///
+    /// This doesn't stop the thread, call [`stop`](#method.stop) first to shut it down.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the bridge was stopped, the channel was closed, or nanomsg was terminated.
+    /// Otherwise the error that made the bridge exit.
+    ///
+    /// # Panics
+    ///
+    /// If the bridge thread panicked.
+    pub fn join(mut self) -> Result<()> {
+        let thread = self.thread.take().expect("bridge thread already joined");
+        match thread.join() {
+            Ok(res) => res,
+            Err(payload) => panic::resume_unwind(payload)
+        }
+    }
+}
+
+impl Drop for BridgeHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Wait until the socket is ready, or the stop interval passes.
+fn wait_ready(sock: &Socket, pollin: bool, pollout: bool) -> Result<bool> {
+    let mut polls = [sock.make_poll(pollin, pollout)];
+    Ok(poll_until(&mut polls, Some(Instant::now() + STOP_INTERVAL))? > 0)
+}
+
+enum ChannelSender {
+    Unbounded(Sender<MessageBuffer>),
+    Bounded(SyncSender<MessageBuffer>)
+}
+
+fn receive_loop<S: SPRecv>(sock: S, tx: ChannelSender, stop: &AtomicBool) -> Result<()> {
+    while !stop.load(Ordering::SeqCst) {
+        if !wait_ready(sock.socket(), true, false)? {
+            continue;
+        }
+        let mut msg = match sock.recv_nb() {
+            Ok(msg) => msg,
+            Err(WOULD_BLOCK) => continue,
+            Err(TERMINATING) => return Ok(()),
+            Err(e) => return Err(e)
+        };
+        match tx {
+            ChannelSender::Unbounded(ref tx) => {
+                if tx.send(msg).is_err() {
+                    return Ok(());
+                }
+            },
+            ChannelSender::Bounded(ref tx) => loop {
+                match tx.try_send(msg) {
+                    Ok(()) => break,
+                    Err(TrySendError::Disconnected(_)) => return Ok(()),
+                    Err(TrySendError::Full(unsent)) => {
+                        if stop.load(Ordering::SeqCst) {
+                            return Ok(());
+                        }
+                        msg = unsent;
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn send_loop<S: SPSend>(sock: S, rx: Receiver<MessageBuffer>, stop: &AtomicBool) -> Result<()> {
+    while !stop.load(Ordering::SeqCst) {
+        let mut msg = match rx.recv_timeout(STOP_INTERVAL) {
+            Ok(msg) => msg,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return Ok(())
+        };
+        loop {
+            match sock.socket().try_send(msg, Flags::DONTWAIT) {
+                Ok(_) => break,
+                Err((WOULD_BLOCK, unsent)) => {
+                    if stop.load(Ordering::SeqCst) {
+                        return Ok(());
+                    }
+                    msg = unsent;
+                    wait_ready(sock.socket(), false, true)?;
+                },
+                Err((TERMINATING, _)) => return Ok(()),
+                Err((e, _)) => return Err(e)
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Forward the messages received on `sock` to an unbounded channel.
+///
+/// The bridge exits when it is stopped, the receiver is dropped, nanomsg is terminated, or
+/// receiving fails.
+pub fn spawn_receiver<S>(sock: S) -> (Receiver<MessageBuffer>, BridgeHandle)
+    where S: SPRecv + Send + 'static
+{
+    let (tx, rx) = mpsc::channel();
+    let handle = BridgeHandle::spawn("nmsg-bridge-recv", move |stop| {
+        receive_loop(sock, ChannelSender::Unbounded(tx), stop)
+    });
+    (rx, handle)
+}
+
+/// Forward the messages received on `sock` to a channel holding at most `capacity` messages.
+///
+/// While the channel is full, no more messages are received from the socket.
+///
+/// # See Also
+///
+/// * [`spawn_receiver`](fn.spawn_receiver.html)
+pub fn spawn_receiver_bounded<S>(sock: S, capacity: usize) -> (Receiver<MessageBuffer>, BridgeHandle)
+    where S: SPRecv + Send + 'static
+{
+    let (tx, rx) = mpsc::sync_channel(capacity);
+    let handle = BridgeHandle::spawn("nmsg-bridge-recv", move |stop| {
+        receive_loop(sock, ChannelSender::Bounded(tx), stop)
+    });
+    (rx, handle)
+}
+
+/// Send the messages written to an unbounded channel on `sock`.
+///
+/// The bridge exits when it is stopped, all senders are dropped and the queued messages have
+/// been sent, nanomsg is terminated, or sending fails.
+pub fn spawn_sender<S>(sock: S) -> (Sender<MessageBuffer>, BridgeHandle)
+    where S: SPSend + Send + 'static
+{
+    let (tx, rx) = mpsc::channel();
+    let handle = BridgeHandle::spawn("nmsg-bridge-send", move |stop| send_loop(sock, rx, stop));
+    (tx, handle)
+}
+
+/// Send the messages written to a channel holding at most `capacity` messages on `sock`.
+///
+/// Writers block while the channel is full, for example because the socket has no peers to
+/// send to.
+///
+/// # See Also
+///
+/// * [`spawn_sender`](fn.spawn_sender.html)
+pub fn spawn_sender_bounded<S>(sock: S, capacity: usize) -> (SyncSender<MessageBuffer>, BridgeHandle)
+    where S: SPSend + Send + 'static
+{
+    let (tx, rx) = mpsc::sync_channel(capacity);
+    let handle = BridgeHandle::spawn("nmsg-bridge-send", move |stop| send_loop(sock, rx, stop));
+    (tx, handle)
+}
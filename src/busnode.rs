@@ -0,0 +1,257 @@
+//! Node identities for bus topologies.
+//!
+//! In a mesh of [`Bus`](../protocol/struct.Bus.html) sockets joined by devices or a
+//! [`Loopback`](../protocol/trait.Loopback.html) device, a node can receive its own messages,
+//! and receive the same message more than once over different paths. A
+//! [`BusNode`](struct.BusNode.html) stamps each message with the id of the node that sent it and
+//! a message id, drops its own echoes and recently seen messages, and keeps track of the nodes
+//! it has heard from.
+//!
+//! # Wire Format
+//!
+//! A message is `[payload][origin: u64][message id: u64]`, with the integers in big endian.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use alloc::MessageBuffer;
+use error::{Result, INVALID};
+use pod::BigEndian;
+use protocol::{Bus, SPRecv, SPSend};
+use random::random_id;
+
+/// The size of the trailer.
+const TRAILER_LEN: usize = 16;
+
+/// The default number of recent messages remembered for duplicate suppression.
+pub const DEFAULT_DEDUP_WINDOW: usize = 4096;
+
+/// The default time a node stays a member after it was last heard from.
+pub const DEFAULT_MEMBERSHIP_TTL: Duration = Duration::from_secs(30);
+
+/// Counters for a [`BusNode`](struct.BusNode.html).
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct BusStats {
+    /// The number of messages sent.
+    pub sent: u64,
+    /// The number of messages received from other nodes and returned.
+    pub received: u64,
+    /// The number of this node's own messages dropped.
+    pub echoes: u64,
+    /// The number of duplicate messages dropped.
+    pub duplicates: u64
+}
+
+/// A message received by a [`BusNode`](struct.BusNode.html).
+#[derive(Debug)]
+pub struct BusMessage {
+    msg: MessageBuffer,
+    origin: u64,
+    id: u64
+}
+
+impl BusMessage {
+    /// The id of the node that sent the message.
+    #[inline]
+    pub fn origin(&self) -> u64 {
+        self.origin
+    }
+
+    /// The id of the message, unique for its origin.
+    #[inline]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The payload of the message.
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        &self.msg
+    }
+
+    /// The payload of the message, without the trailer.
+    #[inline]
+    pub fn into_buffer(self) -> MessageBuffer {
+        self.msg
+    }
+}
+
+/// A bus socket with a node id, echo and duplicate suppression, and a membership view.
+pub struct BusNode {
+    sock: Bus,
+    id: u64,
+    next_id: u64,
+    seen: HashSet<(u64, u64)>,
+    seen_order: VecDeque<(u64, u64)>,
+    dedup_window: usize,
+    members: HashMap<u64, Instant>,
+    membership_ttl: Duration,
+    stats: BusStats
+}
+
+impl BusNode {
+    /// Create a node with a random id.
+    pub fn new(sock: Bus) -> BusNode {
+        BusNode::with_id(sock, random_id())
+    }
+
+    /// Create a node with the given id.
+    ///
+    /// Every node on the bus must have a different id. Message ids start from 1 again, so the
+    /// first messages of a node restarted with the same id may be dropped as duplicates by nodes
+    /// that still remember its previous messages.
+    pub fn with_id(sock: Bus, id: u64) -> BusNode {
+        BusNode {
+            sock,
+            id,
+            next_id: 1,
+            seen: HashSet::new(),
+            seen_order: VecDeque::new(),
+            dedup_window: DEFAULT_DEDUP_WINDOW,
+            members: HashMap::new(),
+            membership_ttl: DEFAULT_MEMBERSHIP_TTL,
+            stats: BusStats::default()
+        }
+    }
+
+    /// Get a reference to the socket, for example to bind or connect it.
+    #[inline]
+    pub fn get_ref(&self) -> &Bus {
+        &self.sock
+    }
+
+    /// The id of this node.
+    #[inline]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Set how many recent messages are remembered to detect duplicates.
+    ///
+    /// A duplicate that arrives after more than this many other messages isn't detected.
+    pub fn set_dedup_window(&mut self, window: usize) {
+        self.dedup_window = window;
+        self.trim_seen();
+    }
+
+    /// Set how long a node stays a member after it was last heard from.
+    pub fn set_membership_ttl(&mut self, ttl: Duration) {
+        self.membership_ttl = ttl;
+    }
+
+    /// The counters of the node.
+    #[inline]
+    pub fn stats(&self) -> BusStats {
+        self.stats
+    }
+
+    /// The ids of the other nodes heard from within the membership time to live, in ascending
+    /// order.
+    pub fn members(&mut self) -> Vec<u64> {
+        let ttl = self.membership_ttl;
+        self.members.retain(|_, heard| heard.elapsed() < ttl);
+        let mut members = self.members.keys().cloned().collect::<Vec<_>>();
+        members.sort_unstable();
+        members
+    }
+
+    /// Return true if a node was heard from within the membership time to live.
+    pub fn is_member(&self, id: u64) -> bool {
+        self.members.get(&id).is_some_and(|heard| heard.elapsed() < self.membership_ttl)
+    }
+
+    /// Send a message to the other nodes, blocking until it can be sent.
+    ///
+    /// # Returns
+    ///
+    /// The id of the message.
+    pub fn send(&mut self, payload: &[u8]) -> Result<u64> {
+        let msg = self.stamp(payload)?;
+        self.sock.send(msg)?;
+        Ok(self.sent())
+    }
+
+    /// Send a message without blocking.
+    ///
+    /// See [`send`](#method.send).
+    pub fn send_nb(&mut self, payload: &[u8]) -> Result<u64> {
+        let msg = self.stamp(payload)?;
+        self.sock.send_nb(msg)?;
+        Ok(self.sent())
+    }
+
+    fn stamp(&self, payload: &[u8]) -> Result<MessageBuffer> {
+        let mut msg = MessageBuffer::new(payload.len() + TRAILER_LEN);
+        msg[..payload.len()].copy_from_slice(payload);
+        msg.write_pod(payload.len(), &BigEndian::new(self.id))?;
+        msg.write_pod(payload.len() + 8, &BigEndian::new(self.next_id))?;
+        Ok(msg)
+    }
+
+    fn sent(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.stats.sent += 1;
+        id
+    }
+
+    /// Receive a message from another node, blocking until one is available.
+    ///
+    /// Echoes and duplicates are dropped without returning.
+    ///
+    /// # Returns
+    ///
+    /// `Err(error::INVALID)` if the message received has no trailer.
+    pub fn recv(&mut self) -> Result<BusMessage> {
+        loop {
+            let msg = self.sock.recv()?;
+            if let Some(msg) = self.accept(msg)? {
+                return Ok(msg);
+            }
+        }
+    }
+
+    /// Receive a message from another node without blocking.
+    ///
+    /// See [`recv`](#method.recv). Returns `Err(error::WOULD_BLOCK)` if only echoes and
+    /// duplicates were waiting.
+    pub fn recv_nb(&mut self) -> Result<BusMessage> {
+        loop {
+            let msg = self.sock.recv_nb()?;
+            if let Some(msg) = self.accept(msg)? {
+                return Ok(msg);
+            }
+        }
+    }
+
+    fn accept(&mut self, mut msg: MessageBuffer) -> Result<Option<BusMessage>> {
+        if msg.len() < TRAILER_LEN {
+            return Err(INVALID);
+        }
+        let len = msg.len() - TRAILER_LEN;
+        let origin = msg.read_pod::<BigEndian<u64>>(len)?.get();
+        let id = msg.read_pod::<BigEndian<u64>>(len + 8)?.get();
+        if origin == self.id {
+            self.stats.echoes += 1;
+            return Ok(None);
+        }
+        if self.seen.contains(&(origin, id)) {
+            self.stats.duplicates += 1;
+            return Ok(None);
+        }
+        self.seen.insert((origin, id));
+        self.seen_order.push_back((origin, id));
+        self.trim_seen();
+        self.members.insert(origin, Instant::now());
+        self.stats.received += 1;
+        msg.resize(len);
+        Ok(Some(BusMessage { msg, origin, id }))
+    }
+
+    fn trim_seen(&mut self) {
+        while self.seen_order.len() > self.dedup_window {
+            if let Some(key) = self.seen_order.pop_front() {
+                self.seen.remove(&key);
+            }
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Typed messages using serde.
+//!
+//! This contains a [`Codec`](trait.Codec.html) trait for serializing values into
+//! [`MessageBuffer`](../alloc/struct.MessageBuffer.html)s and deserializing them again, and a
+//! [`Typed`](struct.Typed.html) socket wrapper that sends and receives values with a codec.
+//!
+//! This module requires the `serde` feature. The provided codecs are each enabled by a separate
+//! feature:
+//!
+//! * [`Bincode`](struct.Bincode.html): `bincode`
+//! * [`Json`](struct.Json.html): `json`
+//! * [`Cbor`](struct.Cbor.html): `cbor`
+//! * [`MessagePack`](struct.MessagePack.html): `msgpack`
+use std::fmt;
+use std::result;
+
+use failure::{self, Fail};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use alloc::MessageBuffer;
+#[cfg(any(feature = "bincode", feature = "json", feature = "cbor", feature = "msgpack"))]
+use alloc::MessageWriter;
+use error;
+use protocol::{SPSocket, SPRecv, SPSend, Req, Rep};
+use socket::Endpoint;
+
+/// Specialized [`Result`](https://doc.rust-lang.org/std/result/enum.Result.html) type for codec
+/// errors.
+pub type Result<T> = result::Result<T, Error>;
+
+/// An error from sending or receiving a typed value.
+#[derive(Debug)]
+pub enum Error {
+    /// The underlying nanomsg operation failed.
+    Nanomsg(error::Error),
+    /// The value couldn't be serialized.
+    Encode(failure::Error),
+    /// The message couldn't be deserialized.
+    Decode(failure::Error)
+}
+
+impl From<error::Error> for Error {
+    fn from(err: error::Error) -> Error {
+        Error::Nanomsg(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Nanomsg(ref e) => e.fmt(f),
+            Error::Encode(ref e) => write!(f, "Encode error: {}", e),
+            Error::Decode(ref e) => write!(f, "Decode error: {}", e)
+        }
+    }
+}
+
+impl Fail for Error {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            Error::Nanomsg(ref e) => Some(e),
+            Error::Encode(ref e) | Error::Decode(ref e) => Some(e.as_fail())
+        }
+    }
+}
+
+/// A serialization format for messages.
+pub trait Codec {
+    /// Serialize a value into a new message.
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<MessageBuffer>;
+
+    /// Deserialize a value from the contents of a message.
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T>;
+}
+
+/// [Bincode](https://docs.rs/bincode) codec.
+///
+/// Requires the `bincode` feature.
+#[cfg(feature = "bincode")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<MessageBuffer> {
+        let size = ::bincode::serialized_size(value).map_err(|e| Error::Encode(e.into()))?;
+        let mut writer = MessageWriter::with_capacity(size as usize);
+        ::bincode::serialize_into(&mut writer, value).map_err(|e| Error::Encode(e.into()))?;
+        Ok(writer.into_buffer())
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        ::bincode::deserialize(bytes).map_err(|e| Error::Decode(e.into()))
+    }
+}
+
+/// JSON codec, using [serde_json](https://docs.rs/serde_json).
+///
+/// Requires the `json` feature.
+#[cfg(feature = "json")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<MessageBuffer> {
+        let mut writer = MessageWriter::new();
+        ::serde_json::to_writer(&mut writer, value).map_err(|e| Error::Encode(e.into()))?;
+        Ok(writer.into_buffer())
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        ::serde_json::from_slice(bytes).map_err(|e| Error::Decode(e.into()))
+    }
+}
+
+/// CBOR codec, using [ciborium](https://docs.rs/ciborium).
+///
+/// Requires the `cbor` feature.
+#[cfg(feature = "cbor")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<MessageBuffer> {
+        let mut writer = MessageWriter::new();
+        ::ciborium::ser::into_writer(value, &mut writer).map_err(|e| Error::Encode(e.into()))?;
+        Ok(writer.into_buffer())
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        ::ciborium::de::from_reader(bytes).map_err(|e| Error::Decode(e.into()))
+    }
+}
+
+/// MessagePack codec, using [rmp-serde](https://docs.rs/rmp-serde).
+///
+/// Structs are encoded as maps with field names, so that fields can be added or reordered
+/// without breaking compatibility.
+///
+/// Requires the `msgpack` feature.
+#[cfg(feature = "msgpack")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<MessageBuffer> {
+        let mut writer = MessageWriter::new();
+        ::rmp_serde::encode::write_named(&mut writer, value).map_err(|e| Error::Encode(e.into()))?;
+        Ok(writer.into_buffer())
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        ::rmp_serde::from_slice(bytes).map_err(|e| Error::Decode(e.into()))
+    }
+}
+
+/// A socket that sends and receives typed values.
+///
+/// This wraps one of the [protocol](../protocol/index.html) sockets, and uses a
+/// [`Codec`](trait.Codec.html) to convert values to and from messages.
+#[derive(Debug)]
+pub struct Typed<S, C> {
+    sock: S,
+    codec: C
+}
+
+impl<S: SPSocket, C: Codec> Typed<S, C> {
+    /// Wrap a socket with a codec.
+    pub fn new(sock: S, codec: C) -> Typed<S, C> {
+        Typed { sock, codec }
+    }
+
+    /// Get a reference to the wrapped socket.
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.sock
+    }
+
+    /// Get a reference to the codec.
+    #[inline]
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Unwrap the socket and codec.
+    pub fn into_inner(self) -> (S, C) {
+        (self.sock, self.codec)
+    }
+
+    /// Bind the wrapped socket to an address.
+    ///
+    /// See [`SPSocket::bind`](../protocol/trait.SPSocket.html#method.bind).
+    #[inline]
+    pub fn bind(&self, addr: &str) -> error::Result<Endpoint> {
+        self.sock.bind(addr)
+    }
+
+    /// Connect the wrapped socket to an address.
+    ///
+    /// See [`SPSocket::connect`](../protocol/trait.SPSocket.html#method.connect).
+    #[inline]
+    pub fn connect(&self, addr: &str) -> error::Result<Endpoint> {
+        self.sock.connect(addr)
+    }
+}
+
+impl<S: SPSend, C: Codec> Typed<S, C> {
+    /// Serialize and send a value.
+    ///
+    /// Blocks until the message can be sent.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes in the message.
+    pub fn send_value<T: Serialize + ?Sized>(&self, value: &T) -> Result<usize> {
+        let msg = self.codec.encode(value)?;
+        Ok(self.sock.send(msg)?)
+    }
+
+    /// Serialize and send a value without blocking.
+    ///
+    /// If sending would block `Err(Error::Nanomsg(error::WOULD_BLOCK))` is returned.
+    pub fn send_value_nb<T: Serialize + ?Sized>(&self, value: &T) -> Result<usize> {
+        let msg = self.codec.encode(value)?;
+        Ok(self.sock.send_nb(msg)?)
+    }
+}
+
+impl<S: SPRecv, C: Codec> Typed<S, C> {
+    /// Receive and deserialize a value.
+    ///
+    /// Blocks until a message can be read.
+    pub fn recv_value<T: DeserializeOwned>(&self) -> Result<T> {
+        let msg = self.sock.recv()?;
+        self.codec.decode(&msg)
+    }
+
+    /// Receive and deserialize a value without blocking.
+    ///
+    /// If receiving would block `Err(Error::Nanomsg(error::WOULD_BLOCK))` is returned.
+    pub fn recv_value_nb<T: DeserializeOwned>(&self) -> Result<T> {
+        let msg = self.sock.recv_nb()?;
+        self.codec.decode(&msg)
+    }
+}
+
+impl<C: Codec> Typed<Req, C> {
+    /// Send a request value and block until we receive a reply value.
+    ///
+    /// See [`Req::request`](../protocol/struct.Req.html#method.request).
+    pub fn request_value<T, R>(&self, request: &T) -> Result<R>
+        where T: Serialize + ?Sized,
+              R: DeserializeOwned
+    {
+        let reply = self.sock.request(self.codec.encode(request)?)?;
+        self.codec.decode(&reply)
+    }
+}
+
+impl<C: Codec> Typed<Rep, C> {
+    /// Reply to a request value.
+    ///
+    /// This will block waiting for a request, and once it receives
+    /// one, will use the supplied function to prepare a response value.
+    ///
+    /// See [`Rep::reply`](../protocol/struct.Rep.html#method.reply).
+    pub fn reply_value<T, R, F, E>(&self, handler: F) -> result::Result<(), E>
+        where T: DeserializeOwned,
+              R: Serialize,
+              F: FnOnce(T) -> result::Result<R, E>,
+              E: From<Error>
+    {
+        let request = self.recv_value()?;
+        let reply = handler(request)?;
+        self.send_value(&reply)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+//! Devices running on a background thread.
+//!
+//! [`SPSocket::device`](../protocol/trait.SPSocket.html#method.device) blocks the calling thread
+//! until nanomsg is terminated with
+//! [`Socket::terminate`](../socket/struct.Socket.html#method.terminate), which closes every socket
+//! in the process. A [`DeviceHandle`](struct.DeviceHandle.html) runs a device on its own thread
+//! instead, and can stop just that device by closing its sockets.
+use std::mem::ManuallyDrop;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use error::{Error, BAD_FILE};
+use protocol::{Loopback, SPSocket};
+use socket::{RawFd, Socket};
+
+/// Who closes the sockets of a device.
+#[derive(Debug, PartialEq)]
+enum State {
+    /// The thread hasn't started the device, and closes the sockets itself if it was stopped.
+    Starting,
+    /// The device has started, and the handle closes the sockets.
+    Running,
+    /// The sockets are closed, or are about to be closed by the thread.
+    Stopped
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    a: RawFd,
+    b: Option<RawFd>
+}
+
+impl Shared {
+    fn close(&self) {
+        unsafe {
+            drop(Socket::from_raw(self.a));
+            if let Some(b) = self.b {
+                drop(Socket::from_raw(b));
+            }
+        }
+    }
+}
+
+/// A handle to a device running on a background thread.
+///
+/// The handle owns the sockets of the device. Dropping the handle stops the device.
+#[derive(Debug)]
+pub struct DeviceHandle {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<Error>>
+}
+
+impl DeviceHandle {
+    /// Start a device forwarding messages between `sock` and `companion`.
+    ///
+    /// Both sockets should be raw sockets (see `new_raw`).
+    ///
+    /// # See Also
+    ///
+    /// * [`SPSocket::device`](../protocol/trait.SPSocket.html#method.device)
+    pub fn spawn<S: SPSocket>(sock: S, companion: S::Companion) -> DeviceHandle {
+        let a = unsafe { sock.into_socket().into_raw_fd() };
+        let b = unsafe { companion.into_socket().into_raw_fd() };
+        DeviceHandle::start(a, Some(b))
+    }
+
+    /// Start a loopback device, forwarding all traffic on `sock` back to it.
+    ///
+    /// # See Also
+    ///
+    /// * [`Loopback::loopback_device`](../protocol/trait.Loopback.html#method.loopback_device)
+    pub fn spawn_loopback<S: Loopback>(sock: S) -> DeviceHandle {
+        let fd = unsafe { sock.into_socket().into_raw_fd() };
+        DeviceHandle::start(fd, None)
+    }
+
+    fn start(a: RawFd, b: Option<RawFd>) -> DeviceHandle {
+        let shared = Arc::new(Shared { state: Mutex::new(State::Starting), a, b });
+        let thread_shared = shared.clone();
+        let thread = thread::Builder::new()
+            .name("nmsg-device".to_string())
+            .spawn(move || {
+                let shared = thread_shared;
+                {
+                    // nanomsg reuses the slots of closed sockets, so a device started after its
+                    // sockets were closed could run on sockets created elsewhere.
+                    let mut state = shared.state.lock().unwrap();
+                    if *state == State::Stopped {
+                        shared.close();
+                        return BAD_FILE;
+                    }
+                    *state = State::Running;
+                }
+                // Once running, the sockets are closed by the handle, not by the thread.
+                let a = ManuallyDrop::new(unsafe { Socket::from_raw(shared.a) });
+                match shared.b {
+                    Some(b) => {
+                        let b = ManuallyDrop::new(unsafe { Socket::from_raw(b) });
+                        Socket::device(&a, &b)
+                    },
+                    None => Socket::loopback_device(&a)
+                }
+            })
+            .expect("failed to spawn device thread");
+        DeviceHandle { shared, thread: Some(thread) }
+    }
+
+    /// Stop the device by closing its sockets.
+    ///
+    /// If the device thread hasn't started the device yet, it closes the sockets instead of
+    /// starting it. Other sockets in the process are unaffected. Calling this more than once has
+    /// no effect.
+    pub fn stop(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        match *state {
+            State::Starting => *state = State::Stopped,
+            State::Running => {
+                // Closing blocks until the device releases the sockets, so their slots can't be
+                // reused while it runs.
+                self.shared.close();
+                *state = State::Stopped;
+            },
+            State::Stopped => {}
+        }
+    }
+
+    /// Return true if the device has exited.
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(|t| t.is_finished())
+    }
+
+    /// Wait for the device to exit.
+    ///
+    /// This doesn't stop the device, call [`stop`](#method.stop) first to shut it down. The
+    /// sockets are closed once the device has exited.
+    ///
+    /// # Returns
+    ///
+    /// The error that ended the device. This is `error::BAD_FILE` if it was stopped with `stop`,
+    /// and `error::TERMINATING` if nanomsg was terminated.
+    ///
+    /// # Panics
+    ///
+    /// If the device thread panicked.
+    pub fn join(mut self) -> Error {
+        let thread = self.thread.take().expect("device thread already joined");
+        let err = thread.join().expect("device thread panicked");
+        self.stop();
+        err
+    }
+}
+
+impl Drop for DeviceHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
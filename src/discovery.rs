@@ -0,0 +1,287 @@
+//! Service discovery using surveys.
+//!
+//! Services register a name, an address, and metadata with an
+//! [`Announcer`](struct.Announcer.html), which answers surveys on a
+//! [`Respondent`](../protocol/struct.Respondent.html) socket. A
+//! [`Resolver`](struct.Resolver.html) sends a survey on a
+//! [`Surveyor`](../protocol/struct.Surveyor.html) socket, collects the answers that arrive
+//! before the survey deadline, and caches them for the time to live given by the announcers.
+//!
+//! Typically every announcer connects to a well known address that the resolver binds, or the
+//! other way around.
+//!
+//! # Wire Format
+//!
+//! Integers are big endian, and strings are UTF-8 prefixed with their length as a `u16`.
+//!
+//! A query is `[version: u8][kind = 0: u8][name: string]`. An answer is
+//! `[version: u8][kind = 1: u8][ttl in milliseconds: u32][count: u16]` followed by `count`
+//! services, each `[name: string][address: string][count: u16]` followed by `count` pairs of
+//! `[key: string][value: string]`.
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use alloc::MessageBuffer;
+use error::{Error, Result, INVALID};
+use pod::{self, BigEndian};
+use protocol::{Respondent, SPRecv, SPSend, Surveyor};
+use wire::Reader;
+
+const VERSION: u8 = 1;
+const KIND_QUERY: u8 = 0;
+const KIND_ANSWER: u8 = 1;
+
+/// The default time answers are cached for.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(30);
+
+/// A registered service.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ServiceInfo {
+    /// The name of the service.
+    pub name: String,
+    /// The address to connect to, for example `tcp://10.0.0.1:5555`.
+    pub address: String,
+    /// Additional information about the service.
+    pub metadata: BTreeMap<String, String>
+}
+
+impl ServiceInfo {
+    /// Create a service without metadata.
+    pub fn new<N: Into<String>, A: Into<String>>(name: N, address: A) -> ServiceInfo {
+        ServiceInfo {
+            name: name.into(),
+            address: address.into(),
+            metadata: BTreeMap::new()
+        }
+    }
+
+    /// Add a metadata entry.
+    pub fn with_metadata<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> ServiceInfo {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) -> Result<()> {
+    if s.len() > u16::MAX as usize {
+        return Err(INVALID);
+    }
+    out.extend_from_slice(pod::bytes_of(&BigEndian::new(s.len() as u16)));
+    out.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+/// Answers discovery queries for the services registered with it.
+pub struct Announcer {
+    sock: Respondent,
+    services: Vec<ServiceInfo>,
+    ttl: Duration
+}
+
+impl Announcer {
+    /// Create an announcer with no services.
+    pub fn new(sock: Respondent) -> Announcer {
+        Announcer { sock, services: Vec::new(), ttl: DEFAULT_TTL }
+    }
+
+    /// Get a reference to the socket, for example to connect it.
+    #[inline]
+    pub fn get_ref(&self) -> &Respondent {
+        &self.sock
+    }
+
+    /// Set how long resolvers may cache the answers.
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        self.ttl = ttl;
+    }
+
+    /// Register a service.
+    ///
+    /// A service with the same name and address replaces the previous registration.
+    pub fn register(&mut self, service: ServiceInfo) {
+        self.services.retain(|s| s.name != service.name || s.address != service.address);
+        self.services.push(service);
+    }
+
+    /// Unregister the service with this name and address.
+    ///
+    /// # Returns
+    ///
+    /// `false` if no such service was registered.
+    pub fn unregister(&mut self, name: &str, address: &str) -> bool {
+        let len = self.services.len();
+        self.services.retain(|s| s.name != name || s.address != address);
+        self.services.len() != len
+    }
+
+    /// Wait for a query, and answer it if any of the registered services match.
+    pub fn respond(&self) -> Result<()> {
+        let query = self.sock.recv()?;
+        self.answer(&query)
+    }
+
+    /// Answer a query if one is waiting, without blocking.
+    ///
+    /// Returns `Err(error::WOULD_BLOCK)` if there is no query.
+    pub fn respond_nb(&self) -> Result<()> {
+        let query = self.sock.recv_nb()?;
+        self.answer(&query)
+    }
+
+    /// Answer queries until an error occurs.
+    ///
+    /// # Returns
+    ///
+    /// The error that stopped the announcer, for example `error::TERMINATING`.
+    pub fn run(&self) -> Error {
+        loop {
+            match self.respond() {
+                // Malformed queries are ignored
+                Ok(()) | Err(INVALID) => {},
+                Err(e) => return e
+            }
+        }
+    }
+
+    fn answer(&self, query: &[u8]) -> Result<()> {
+        let mut reader = Reader::new(query);
+        if reader.u8()? != VERSION || reader.u8()? != KIND_QUERY {
+            return Err(INVALID);
+        }
+        let name = reader.str()?.to_string();
+        let matches = self.services.iter().filter(|s| s.name == name).collect::<Vec<_>>();
+        if matches.is_empty() {
+            // Surveys don't need to be answered
+            return Ok(());
+        }
+
+        let ttl = self.ttl.as_millis().min(u128::from(u32::MAX)) as u32;
+        let mut out = vec![VERSION, KIND_ANSWER];
+        out.extend_from_slice(pod::bytes_of(&BigEndian::new(ttl)));
+        out.extend_from_slice(pod::bytes_of(&BigEndian::new(matches.len() as u16)));
+        for service in matches {
+            put_str(&mut out, &service.name)?;
+            put_str(&mut out, &service.address)?;
+            out.extend_from_slice(pod::bytes_of(&BigEndian::new(service.metadata.len() as u16)));
+            for (key, value) in &service.metadata {
+                put_str(&mut out, key)?;
+                put_str(&mut out, value)?;
+            }
+        }
+        self.sock.send(MessageBuffer::from(out))?;
+        Ok(())
+    }
+}
+
+fn parse_answer(answer: &[u8]) -> Result<(Duration, Vec<ServiceInfo>)> {
+    let mut reader = Reader::new(answer);
+    if reader.u8()? != VERSION || reader.u8()? != KIND_ANSWER {
+        return Err(INVALID);
+    }
+    let ttl = Duration::from_millis(u64::from(reader.u32()?));
+    let count = reader.u16()?;
+    let mut services = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let name = reader.str()?.to_string();
+        let address = reader.str()?.to_string();
+        let mut metadata = BTreeMap::new();
+        for _ in 0..reader.u16()? {
+            let key = reader.str()?.to_string();
+            metadata.insert(key, reader.str()?.to_string());
+        }
+        services.push(ServiceInfo { name, address, metadata });
+    }
+    Ok((ttl, services))
+}
+
+struct CacheEntry {
+    services: Vec<ServiceInfo>,
+    expires: Instant
+}
+
+/// Looks up services by name, caching the answers.
+pub struct Resolver {
+    sock: Surveyor,
+    cache: HashMap<String, CacheEntry>
+}
+
+impl Resolver {
+    /// Create a resolver with an empty cache.
+    ///
+    /// Lookups wait for answers until the survey deadline of `sock` expires (see
+    /// [`Surveyor::set_survey_deadline`](../protocol/struct.Surveyor.html#method.set_survey_deadline)).
+    pub fn new(sock: Surveyor) -> Resolver {
+        Resolver { sock, cache: HashMap::new() }
+    }
+
+    /// Get a reference to the socket, for example to bind it.
+    #[inline]
+    pub fn get_ref(&self) -> &Surveyor {
+        &self.sock
+    }
+
+    /// Find the instances of a service.
+    ///
+    /// Cached answers are returned if they haven't expired. Otherwise a survey is sent, which
+    /// blocks until the survey deadline. An empty result isn't cached, so the next lookup
+    /// surveys again.
+    ///
+    /// # Returns
+    ///
+    /// The instances of the service, without duplicate addresses, in no particular order.
+    pub fn lookup(&mut self, name: &str) -> Result<Vec<ServiceInfo>> {
+        if let Some(entry) = self.cache.get(name) {
+            if entry.expires > Instant::now() {
+                return Ok(entry.services.clone());
+            }
+        }
+
+        let mut query = vec![VERSION, KIND_QUERY];
+        put_str(&mut query, name)?;
+        let answers = self.sock.survey(MessageBuffer::from(query))?;
+
+        let mut ttl: Option<Duration> = None;
+        let mut services: Vec<ServiceInfo> = Vec::new();
+        // Malformed answers are ignored
+        for (answer_ttl, answer) in answers.iter().filter_map(|a| parse_answer(a).ok()) {
+            ttl = Some(ttl.map_or(answer_ttl, |t| t.min(answer_ttl)));
+            for service in answer {
+                if service.name == name && !services.iter().any(|s| s.address == service.address) {
+                    services.push(service);
+                }
+            }
+        }
+
+        match ttl {
+            Some(ttl) if !services.is_empty() => {
+                self.cache.insert(name.to_string(), CacheEntry {
+                    services: services.clone(),
+                    expires: Instant::now() + ttl
+                });
+            },
+            _ => {
+                self.cache.remove(name);
+            }
+        }
+        Ok(services)
+    }
+
+    /// Find the addresses of the instances of a service.
+    ///
+    /// The addresses can be passed to
+    /// [`SPSocket::connect`](../protocol/trait.SPSocket.html#method.connect). See
+    /// [`lookup`](#method.lookup).
+    pub fn lookup_addrs(&mut self, name: &str) -> Result<Vec<String>> {
+        Ok(self.lookup(name)?.into_iter().map(|s| s.address).collect())
+    }
+
+    /// Remove a service from the cache, so that the next lookup surveys again.
+    pub fn invalidate(&mut self, name: &str) {
+        self.cache.remove(name);
+    }
+
+    /// Remove all services from the cache.
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+}
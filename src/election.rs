@@ -0,0 +1,348 @@
+//! Leader election over a bus.
+//!
+//! A [`LeaderElection`](struct.LeaderElection.html) elects one active instance among several
+//! nodes connected by [`Bus`](../protocol/struct.Bus.html) sockets, with the others standing by.
+//!
+//! Every node has a unique id. Elections happen in numbered terms: a node that hasn't heard from
+//! a leader within the election timeout starts a new term by claiming leadership, and becomes
+//! leader if no stronger claim or leader is heard within another election timeout. The leader
+//! then sends heartbeats every heartbeat interval. Followers wait a random extra of up to half
+//! the election timeout, so that they rarely claim at the same time, and a node that accepts a
+//! claim waits twice the election timeout, so that it doesn't give up on the candidate before
+//! the candidate's first heartbeat. Claims and heartbeats are ranked by term, then
+//! by node id, so the node with the higher id wins when two nodes claim the same term, and a
+//! leader steps down as soon as it hears a stronger claim or leader.
+//!
+//! A bus socket only delivers messages to the sockets it is directly connected to, so every node
+//! should be connected to every other node, or to a shared
+//! [`Loopback`](../protocol/trait.Loopback.html) device.
+//!
+//! # Partitions
+//!
+//! This isn't a consensus protocol, and there is no quorum. When the network is partitioned,
+//! each side that doesn't hear the leader elects its own within about two and a half times the
+//! election timeout, so there can be one leader per partition. When the partition heals, the leaders hear
+//! each other's heartbeats, and every leader but the one with the highest term and id steps down
+//! with a `LostLeadership` event. Applications that can't tolerate two active instances for that
+//! long need an external fence.
+//!
+//! A node that misses heartbeats because of a slow network starts a new term, which makes the
+//! current leader step down even if it is healthy.
+//!
+//! # Wire Format
+//!
+//! A message is `[kind: u8][term: u64][node: u64]`, with the integers in big endian. The kind is
+//! 0 for a heartbeat and 1 for a claim.
+use std::time::{Duration, Instant};
+
+use alloc::MessageBuffer;
+use error::{Result, WOULD_BLOCK};
+use poller::poll_readable;
+use pod::BigEndian;
+use protocol::{Bus, SPRecv, SPSend, SPSocket};
+use random::random_id;
+
+const KIND_HEARTBEAT: u8 = 0;
+const KIND_CLAIM: u8 = 1;
+
+/// The size of a message.
+const MESSAGE_LEN: usize = 17;
+
+/// The default time between heartbeats from the leader.
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The default time without heartbeats after which a new election starts.
+pub const DEFAULT_ELECTION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The role of a node in the election.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Role {
+    /// Following a leader, or waiting for one.
+    Follower,
+    /// Claiming leadership for a new term.
+    Candidate,
+    /// The leader of the current term.
+    Leader
+}
+
+/// A change reported by a [`LeaderElection`](struct.LeaderElection.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ElectionEvent {
+    /// This node became the leader.
+    BecameLeader {
+        /// The term this node leads.
+        term: u64
+    },
+    /// This node stopped being the leader.
+    LostLeadership {
+        /// The term this node led.
+        term: u64
+    },
+    /// Another node became the leader, or the leader was lost.
+    LeaderChanged {
+        /// The id of the new leader, or `None` if no leader is known.
+        leader: Option<u64>,
+        /// The current term.
+        term: u64
+    }
+}
+
+/// Takes part in electing a leader among the nodes on a bus.
+///
+/// [`poll`](#method.poll) has to be called continuously, since it sends the claims and
+/// heartbeats, and checks the timeouts.
+pub struct LeaderElection {
+    sock: Bus,
+    id: u64,
+    role: Role,
+    term: u64,
+    leader: Option<u64>,
+    timer: Instant,
+    heartbeat_interval: Duration,
+    election_timeout: Duration
+}
+
+impl LeaderElection {
+    /// Create a follower that doesn't know any leader.
+    ///
+    /// Every node must have a different id. With no other nodes, this node becomes the leader
+    /// after two to two and a half times the election timeout.
+    pub fn new(sock: Bus, id: u64) -> LeaderElection {
+        let now = Instant::now();
+        let mut election = LeaderElection {
+            sock,
+            id,
+            role: Role::Follower,
+            term: 0,
+            leader: None,
+            timer: now,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            election_timeout: DEFAULT_ELECTION_TIMEOUT
+        };
+        election.timer = now + election.follower_timeout();
+        election
+    }
+
+    /// Get a reference to the socket, for example to bind or connect it.
+    #[inline]
+    pub fn get_ref(&self) -> &Bus {
+        &self.sock
+    }
+
+    /// Set how often the leader sends heartbeats.
+    ///
+    /// It should be well below the election timeout, and the same on every node.
+    pub fn set_heartbeat_interval(&mut self, interval: Duration) {
+        self.heartbeat_interval = interval;
+    }
+
+    /// Set how long a node waits for a leader before claiming leadership, and how long it
+    /// claims before becoming leader.
+    ///
+    /// It should be the same on every node.
+    pub fn set_election_timeout(&mut self, timeout: Duration) {
+        self.election_timeout = timeout;
+        match self.role {
+            Role::Follower => self.timer = Instant::now() + self.follower_timeout(),
+            Role::Candidate => self.timer = Instant::now() + timeout,
+            Role::Leader => {}
+        }
+    }
+
+    /// The id of this node.
+    #[inline]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The role of this node.
+    #[inline]
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Return true if this node is the leader.
+    #[inline]
+    pub fn is_leader(&self) -> bool {
+        self.role == Role::Leader
+    }
+
+    /// The id of the current leader, which may be this node.
+    #[inline]
+    pub fn leader(&self) -> Option<u64> {
+        self.leader
+    }
+
+    /// The current term.
+    #[inline]
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    /// Handle messages and timeouts until something changes, or the timeout expires.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout`: How long to wait for a change. `None` waits forever.
+    ///
+    /// # Returns
+    ///
+    /// The changes, in the order they happened, which is empty if the timeout expired.
+    pub fn poll(&mut self, timeout: Option<Duration>) -> Result<Vec<ElectionEvent>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut events = Vec::new();
+        loop {
+            self.receive(&mut events)?;
+            if Instant::now() >= self.timer {
+                self.expire(&mut events)?;
+            }
+            if !events.is_empty() {
+                return Ok(events);
+            }
+
+            let now = Instant::now();
+            let mut wait = self.timer.saturating_duration_since(now);
+            if let Some(d) = deadline {
+                let left = d.saturating_duration_since(now);
+                if left == Duration::from_secs(0) {
+                    return Ok(events);
+                }
+                wait = wait.min(left);
+            }
+            poll_readable(self.sock.socket(), Some(Instant::now() + wait))?;
+        }
+    }
+
+    /// How long a follower waits for a leader: the election timeout, plus a random extra of up
+    /// to half of it.
+    fn follower_timeout(&self) -> Duration {
+        let max = self.election_timeout.as_nanos() / 2;
+        if max == 0 {
+            return self.election_timeout;
+        }
+        self.election_timeout + Duration::from_nanos((u128::from(random_id()) % max) as u64)
+    }
+
+    fn send(&self, kind: u8) -> Result<()> {
+        let mut msg = MessageBuffer::new(MESSAGE_LEN);
+        msg[0] = kind;
+        msg.write_pod(1, &BigEndian::new(self.term))?;
+        msg.write_pod(9, &BigEndian::new(self.id))?;
+        // A lost heartbeat or claim is repeated, so there is no need to block
+        match self.sock.send_nb(msg) {
+            Ok(_) | Err(WOULD_BLOCK) => Ok(()),
+            Err(e) => Err(e)
+        }
+    }
+
+    fn receive(&mut self, events: &mut Vec<ElectionEvent>) -> Result<()> {
+        loop {
+            let msg = match self.sock.recv_nb() {
+                Ok(msg) => msg,
+                Err(WOULD_BLOCK) => return Ok(()),
+                Err(e) => return Err(e)
+            };
+            // Malformed messages and echoes of our own messages are ignored
+            if msg.len() != MESSAGE_LEN {
+                continue;
+            }
+            let term = msg.read_pod::<BigEndian<u64>>(1)?.get();
+            let node = msg.read_pod::<BigEndian<u64>>(9)?.get();
+            if node == self.id {
+                continue;
+            }
+            match msg[0] {
+                KIND_HEARTBEAT => self.on_heartbeat(term, node, events)?,
+                KIND_CLAIM => self.on_claim(term, node, events)?,
+                _ => {}
+            }
+        }
+    }
+
+    /// Return true if `(term, node)` outranks this node in its current term.
+    fn outranks(&self, term: u64, node: u64) -> bool {
+        (term, node) > (self.term, self.id)
+    }
+
+    fn on_heartbeat(&mut self, term: u64, node: u64, events: &mut Vec<ElectionEvent>) -> Result<()> {
+        match self.role {
+            Role::Leader | Role::Candidate if !self.outranks(term, node) => {
+                // Assert our own stronger claim, so that the other node gives up
+                let kind = if self.role == Role::Leader { KIND_HEARTBEAT } else { KIND_CLAIM };
+                return self.send(kind);
+            },
+            Role::Follower => {
+                let weaker = term < self.term
+                    || (term == self.term && self.leader.is_some_and(|leader| node < leader));
+                if weaker {
+                    return Ok(());
+                }
+            },
+            _ => {}
+        }
+        self.step_down(events);
+        self.term = term;
+        self.timer = Instant::now() + self.follower_timeout();
+        if self.leader != Some(node) {
+            self.leader = Some(node);
+            events.push(ElectionEvent::LeaderChanged { leader: Some(node), term });
+        }
+        Ok(())
+    }
+
+    fn on_claim(&mut self, term: u64, node: u64, events: &mut Vec<ElectionEvent>) -> Result<()> {
+        let stronger = match self.role {
+            Role::Follower => term > self.term,
+            Role::Leader | Role::Candidate => self.outranks(term, node)
+        };
+        if !stronger {
+            if self.role == Role::Leader {
+                return self.send(KIND_HEARTBEAT);
+            }
+            return Ok(());
+        }
+        self.step_down(events);
+        self.term = term;
+        // The candidate waits an election timeout before its first heartbeat, so waiting only as
+        // long would race it and start yet another term
+        self.timer = Instant::now() + self.election_timeout + self.follower_timeout();
+        if self.leader.take().is_some() {
+            events.push(ElectionEvent::LeaderChanged { leader: None, term });
+        }
+        Ok(())
+    }
+
+    /// Become a follower, reporting the loss of leadership if this node was the leader.
+    fn step_down(&mut self, events: &mut Vec<ElectionEvent>) {
+        if self.role == Role::Leader {
+            events.push(ElectionEvent::LostLeadership { term: self.term });
+        }
+        self.role = Role::Follower;
+    }
+
+    fn expire(&mut self, events: &mut Vec<ElectionEvent>) -> Result<()> {
+        let now = Instant::now();
+        match self.role {
+            Role::Follower => {
+                self.role = Role::Candidate;
+                self.term += 1;
+                if self.leader.take().is_some() {
+                    events.push(ElectionEvent::LeaderChanged { leader: None, term: self.term });
+                }
+                self.timer = now + self.election_timeout;
+                self.send(KIND_CLAIM)
+            },
+            Role::Candidate => {
+                self.role = Role::Leader;
+                self.leader = Some(self.id);
+                events.push(ElectionEvent::BecameLeader { term: self.term });
+                self.timer = now + self.heartbeat_interval;
+                self.send(KIND_HEARTBEAT)
+            },
+            Role::Leader => {
+                self.timer = now + self.heartbeat_interval;
+                self.send(KIND_HEARTBEAT)
+            }
+        }
+    }
+}
@@ -8,6 +8,7 @@
 //! documentation (as of 2017-11-22).
 use std::ffi::{CStr, NulError};
 use std::fmt;
+use std::io;
 use std::result;
 
 use failure::Fail;
@@ -40,6 +41,23 @@ impl From<NulError> for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error(err.raw_os_error().unwrap_or(::libc::EIO))
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        if err.0 < posix_consts::NN_HAUSNUMERO {
+            io::Error::from_raw_os_error(err.0)
+        } else {
+            // nanomsg specific errors don't have an OS equivalent
+            io::Error::other(err.to_string())
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let message = unsafe { CStr::from_ptr(nn_strerror(self.0)) };
@@ -0,0 +1,183 @@
+//! A device that merges several inputs into one output.
+//!
+//! [`SPSocket::device`](../protocol/trait.SPSocket.html#method.device) connects exactly two
+//! sockets. A [`FanIn`](struct.FanIn.html) receives from any number of sockets, for example
+//! several `Pull` or `Sub` sockets bound on different transports, and sends everything it
+//! receives on a single output socket.
+use std::time::{Duration, Instant};
+
+use error::{Error, Result, INVALID, WOULD_BLOCK};
+use poller::poll_until;
+use protocol::{SPRecv, SPSend};
+use socket::{Domain, Flags, Socket};
+
+/// How a [`FanIn`](struct.FanIn.html) chooses between inputs that are ready at the same time.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fairness {
+    /// Forward one message from each ready input in turn, starting from a different input each
+    /// time.
+    RoundRobin,
+    /// Always forward from the ready input that was added first.
+    ///
+    /// Inputs added later only get through while the earlier inputs are idle.
+    Priority
+}
+
+/// Counters for one input of a [`FanIn`](struct.FanIn.html).
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct InputStats {
+    /// The number of messages received.
+    pub received: u64,
+    /// The number of messages sent on the output.
+    pub forwarded: u64,
+    /// The number of messages that couldn't be sent on the output.
+    pub failed: u64,
+    /// The number of bytes sent on the output.
+    pub bytes: u64
+}
+
+/// A device forwarding messages from many inputs to one output.
+///
+/// The SP headers of the messages are preserved, like with a device, so the inputs and the
+/// output must be raw sockets (see `new_raw`).
+pub struct FanIn {
+    inputs: Vec<Socket>,
+    stats: Vec<InputStats>,
+    output: Socket,
+    fairness: Fairness,
+    next: usize
+}
+
+impl FanIn {
+    /// Create a fan in with no inputs, that sends on `output`.
+    ///
+    /// # Returns
+    ///
+    /// `Err(error::INVALID)` if `output` isn't a raw socket.
+    pub fn new<S: SPSend>(output: S, fairness: Fairness) -> Result<FanIn> {
+        if output.domain() != Domain::SPRaw {
+            return Err(INVALID);
+        }
+        Ok(FanIn {
+            inputs: Vec::new(),
+            stats: Vec::new(),
+            output: output.into_socket(),
+            fairness,
+            next: 0
+        })
+    }
+
+    /// Add an input.
+    ///
+    /// # Returns
+    ///
+    /// The index of the input, which is its position in [`stats`](#method.stats). With
+    /// `Fairness::Priority`, lower indices have higher priority.
+    /// `Err(error::INVALID)` if `input` isn't a raw socket.
+    pub fn add_input<S: SPRecv>(&mut self, input: S) -> Result<usize> {
+        if input.domain() != Domain::SPRaw {
+            return Err(INVALID);
+        }
+        self.inputs.push(input.into_socket());
+        self.stats.push(InputStats::default());
+        Ok(self.inputs.len() - 1)
+    }
+
+    /// The number of inputs.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// Return true if there are no inputs.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// The counters of each input, by index.
+    #[inline]
+    pub fn stats(&self) -> &[InputStats] {
+        &self.stats
+    }
+
+    /// Forward messages until an error occurs.
+    ///
+    /// # Returns
+    ///
+    /// The error that stopped the fan in, for example `error::TERMINATING` once
+    /// [`Socket::terminate`](../socket/struct.Socket.html#method.terminate) is called.
+    pub fn run(&mut self) -> Error {
+        loop {
+            if let Err(e) = self.step(None) {
+                return e;
+            }
+        }
+    }
+
+    /// Wait until at least one input is ready, and forward according to the fairness policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout`: How long to wait for a message. `None` waits forever.
+    ///
+    /// # Returns
+    ///
+    /// The number of messages forwarded, which is 0 if the timeout expired.
+    /// `Err(error::INVALID)` if there are no inputs.
+    pub fn step(&mut self, timeout: Option<Duration>) -> Result<usize> {
+        if self.inputs.is_empty() {
+            return Err(INVALID);
+        }
+        let mut polls = self.inputs.iter()
+            .map(|sock| sock.make_poll(true, false))
+            .collect::<Vec<_>>();
+        poll_until(&mut polls, timeout.map(|t| Instant::now() + t))?;
+
+        let len = self.inputs.len();
+        let mut count = 0;
+        match self.fairness {
+            Fairness::RoundRobin => {
+                let start = self.next % len;
+                self.next = start + 1;
+                for i in (start..len).chain(0..start) {
+                    if polls[i].can_receive() && self.forward(i)? {
+                        count += 1;
+                    }
+                }
+            },
+            Fairness::Priority => {
+                for (i, poll) in polls.iter().enumerate() {
+                    if poll.can_receive() && self.forward(i)? {
+                        count += 1;
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Forward a single message from an input, returning false if none was available.
+    fn forward(&mut self, index: usize) -> Result<bool> {
+        let stats = &mut self.stats[index];
+        let (body, header) = match self.inputs[index].recv_msg(Flags::DONTWAIT) {
+            Ok(msg) => msg,
+            Err(WOULD_BLOCK) => return Ok(false),
+            Err(e) => return Err(e)
+        };
+        stats.received += 1;
+        // Sending blocks, so that backpressure from the output propagates to the inputs.
+        match self.output.send_msg(body, header, Flags::empty()) {
+            Ok(size) => {
+                stats.forwarded += 1;
+                stats.bytes += size as u64;
+                Ok(true)
+            },
+            Err(e) => {
+                stats.failed += 1;
+                Err(e)
+            }
+        }
+    }
+}
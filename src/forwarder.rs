@@ -0,0 +1,181 @@
+//! A device that forwards messages in userspace.
+//!
+//! [`SPSocket::device`](../protocol/trait.SPSocket.html#method.device) forwards messages inside
+//! nanomsg, so they can't be observed. A [`Forwarder`](struct.Forwarder.html) does the same
+//! job with [`Socket::recv_msg`](../socket/struct.Socket.html#method.recv_msg) and
+//! [`Socket::send_msg`](../socket/struct.Socket.html#method.send_msg), passing every message
+//! through a closure that can forward it unchanged, replace it, or drop it.
+//!
+//! The SP headers of each message are preserved, so request/reply and survey topologies keep
+//! working through a forwarder, as long as replies are not dropped.
+use std::time::{Duration, Instant};
+
+use nanomsg_sys::NN_RCVFD;
+
+use alloc::MessageBuffer;
+use error::{Error, Result, INVALID, WOULD_BLOCK};
+use poller::{has_poll_fd, poll_until};
+use protocol::SPSocket;
+use socket::{Domain, Flags, Socket};
+
+/// The direction a message is travelling through a [`Forwarder`](struct.Forwarder.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// From the first socket to the second.
+    Forward,
+    /// From the second socket to the first.
+    Backward
+}
+
+/// Counters for one direction of a [`Forwarder`](struct.Forwarder.html).
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct DirectionStats {
+    /// The number of messages received.
+    pub received: u64,
+    /// The number of messages sent on.
+    pub forwarded: u64,
+    /// The number of messages dropped by the closure.
+    pub dropped: u64,
+    /// The number of messages that couldn't be sent.
+    pub failed: u64,
+    /// The number of bytes sent on.
+    pub bytes: u64
+}
+
+/// Counters for both directions of a [`Forwarder`](struct.Forwarder.html).
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ForwarderStats {
+    /// Messages travelling from the first socket to the second.
+    pub forward: DirectionStats,
+    /// Messages travelling from the second socket to the first.
+    pub backward: DirectionStats
+}
+
+/// A device that passes every message through a closure.
+///
+/// The closure is called with the direction and body of each message. Returning `Some` sends
+/// the returned buffer on (with the headers of the received message), returning `None` drops
+/// the message.
+pub struct Forwarder<F> {
+    a: Socket,
+    b: Socket,
+    // Whether each socket can receive, since nn_poll fails for sockets that can't.
+    a_recv: bool,
+    b_recv: bool,
+    filter: F,
+    stats: ForwarderStats
+}
+
+impl<F> Forwarder<F> where F: FnMut(Direction, MessageBuffer) -> Option<MessageBuffer> {
+    /// Create a forwarder between `sock` and `companion`.
+    ///
+    /// # Returns
+    ///
+    /// `Err(error::INVALID)` if either socket isn't a raw socket (see `new_raw`).
+    pub fn new<S: SPSocket>(sock: S, companion: S::Companion, filter: F) -> Result<Forwarder<F>> {
+        if sock.domain() != Domain::SPRaw || companion.domain() != Domain::SPRaw {
+            return Err(INVALID);
+        }
+        let a = sock.into_socket();
+        let b = companion.into_socket();
+        Ok(Forwarder {
+            a_recv: has_poll_fd(&a, NN_RCVFD)?,
+            b_recv: has_poll_fd(&b, NN_RCVFD)?,
+            a,
+            b,
+            filter,
+            stats: ForwarderStats::default()
+        })
+    }
+
+    /// Get the message counters.
+    #[inline]
+    pub fn stats(&self) -> &ForwarderStats {
+        &self.stats
+    }
+
+    /// Forward messages until an error occurs.
+    ///
+    /// # Returns
+    ///
+    /// The error that stopped the forwarder, for example `error::TERMINATING` once
+    /// [`Socket::terminate`](../socket/struct.Socket.html#method.terminate) is called.
+    pub fn run(&mut self) -> Error {
+        loop {
+            if let Err(e) = self.step(None) {
+                return e;
+            }
+        }
+    }
+
+    /// Wait until a message can be received on either socket, and forward it.
+    ///
+    /// At most one message is forwarded in each direction, so that a busy direction doesn't
+    /// starve the other.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout`: How long to wait for a message. `None` waits forever.
+    ///
+    /// # Returns
+    ///
+    /// The number of messages received, which is 0 if the timeout expired.
+    pub fn step(&mut self, timeout: Option<Duration>) -> Result<usize> {
+        let mut polls = Vec::with_capacity(2);
+        let mut directions = Vec::with_capacity(2);
+        if self.a_recv {
+            polls.push(self.a.make_poll(true, false));
+            directions.push(Direction::Forward);
+        }
+        if self.b_recv {
+            polls.push(self.b.make_poll(true, false));
+            directions.push(Direction::Backward);
+        }
+        if polls.is_empty() {
+            return Err(INVALID);
+        }
+
+        poll_until(&mut polls, timeout.map(|t| Instant::now() + t))?;
+
+        let mut count = 0;
+        for (poll, &direction) in polls.iter().zip(&directions) {
+            if poll.can_receive() && self.forward(direction)? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Forward a single message, returning false if none was available.
+    fn forward(&mut self, direction: Direction) -> Result<bool> {
+        let (from, to, stats) = match direction {
+            Direction::Forward => (&self.a, &self.b, &mut self.stats.forward),
+            Direction::Backward => (&self.b, &self.a, &mut self.stats.backward)
+        };
+        let (body, header) = match from.recv_msg(Flags::DONTWAIT) {
+            Ok(msg) => msg,
+            Err(WOULD_BLOCK) => return Ok(false),
+            Err(e) => return Err(e)
+        };
+        stats.received += 1;
+        let body = match (self.filter)(direction, body) {
+            Some(body) => body,
+            None => {
+                stats.dropped += 1;
+                return Ok(true);
+            }
+        };
+        // Like nn_device, sending blocks, so that backpressure propagates to the sender.
+        match to.send_msg(body, header, Flags::empty()) {
+            Ok(size) => {
+                stats.forwarded += 1;
+                stats.bytes += size as u64;
+                Ok(true)
+            },
+            Err(e) => {
+                stats.failed += 1;
+                Err(e)
+            }
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Peer liveness detection for pair and bus sockets.
+//!
+//! nanomsg reconnects transparently, so a socket can't tell whether the process on the other end
+//! is still running, or has hung. A [`Heartbeat`](struct.Heartbeat.html) wraps a
+//! [`Pair`](../protocol/struct.Pair.html) or [`Bus`](../protocol/struct.Bus.html) socket, sends a
+//! reserved heartbeat frame whenever it hasn't sent anything for an interval, and considers the
+//! peer lost once nothing has been received for a number of intervals.
+//!
+//! Both ends must use a `Heartbeat`. The heartbeat frame is the 8 bytes
+//! [`HEARTBEAT_FRAME`](constant.HEARTBEAT_FRAME.html); an application message with exactly
+//! these bytes would be mistaken for a heartbeat and dropped.
+//!
+//! On a bus socket, any message from any node keeps the peer alive, so this detects losing all
+//! the other nodes rather than a single one.
+use std::time::{Duration, Instant};
+
+use alloc::MessageBuffer;
+use error::{Error, Result, TIMED_OUT, WOULD_BLOCK};
+use poller::poll_readable;
+use protocol::{Loopback, SPRecv, SPSend};
+
+/// The reserved frame sent as a heartbeat.
+pub const HEARTBEAT_FRAME: &[u8] = b"\0nmsg-hb";
+
+/// The default heartbeat interval.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The default number of missed heartbeats after which the peer is considered lost.
+pub const DEFAULT_MAX_MISSES: u32 = 3;
+
+type PeerLost = Box<dyn FnMut()>;
+
+/// A pair or bus socket that sends heartbeats and tracks whether the peer is alive.
+///
+/// [`tick`](#method.tick) has to be called regularly, at least once per interval, for
+/// heartbeats to be sent and liveness to be checked. [`recv`](#method.recv) does this while it
+/// waits.
+pub struct Heartbeat<S> {
+    sock: S,
+    interval: Duration,
+    max_misses: u32,
+    last_sent: Option<Instant>,
+    last_received: Option<Instant>,
+    alive: bool,
+    peer_lost: Option<PeerLost>,
+    /// An error from a tick after a message was received, returned by the next receive.
+    deferred: Option<Error>
+}
+
+impl<S: SPSend + SPRecv + Loopback> Heartbeat<S> {
+    /// Create a heartbeat layer over `sock`.
+    ///
+    /// The peer isn't considered alive until something has been received from it.
+    pub fn new(sock: S) -> Heartbeat<S> {
+        Heartbeat {
+            sock,
+            interval: DEFAULT_INTERVAL,
+            max_misses: DEFAULT_MAX_MISSES,
+            last_sent: None,
+            last_received: None,
+            alive: false,
+            peer_lost: None,
+            deferred: None
+        }
+    }
+
+    /// Get a reference to the socket, for example to bind or connect it.
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.sock
+    }
+
+    /// Set how often heartbeats are sent.
+    ///
+    /// The peer should use the same interval.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Set how many heartbeat intervals may pass without receiving anything before the peer is
+    /// considered lost.
+    pub fn set_max_misses(&mut self, misses: u32) {
+        self.max_misses = misses.max(1);
+    }
+
+    /// Set the callback called when the peer is lost.
+    ///
+    /// The callback is called once each time the peer goes from alive to lost, from
+    /// [`tick`](#method.tick) or [`recv`](#method.recv).
+    pub fn on_peer_lost<F: FnMut() + 'static>(&mut self, callback: F) {
+        self.peer_lost = Some(Box::new(callback));
+    }
+
+    /// Return true if something has been received from the peer recently enough.
+    pub fn peer_alive(&self) -> bool {
+        self.last_received.is_some_and(|t| t.elapsed() < self.window())
+    }
+
+    fn window(&self) -> Duration {
+        self.interval * self.max_misses
+    }
+
+    /// Send a heartbeat if one is due, and check whether the peer has been lost.
+    ///
+    /// Heartbeats are sent without blocking, and dropped if the socket can't send.
+    pub fn tick(&mut self) -> Result<()> {
+        let now = Instant::now();
+        if self.last_sent.is_none_or(|t| now.duration_since(t) >= self.interval) {
+            match self.sock.send_buf_nb(HEARTBEAT_FRAME) {
+                Ok(_) | Err(WOULD_BLOCK) => self.last_sent = Some(now),
+                Err(e) => return Err(e)
+            }
+        }
+        if self.alive && !self.peer_alive() {
+            self.alive = false;
+            if let Some(ref mut peer_lost) = self.peer_lost {
+                peer_lost();
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a message, blocking until it can be sent.
+    ///
+    /// Messages count as heartbeats, so no heartbeat is sent while messages are sent often
+    /// enough.
+    pub fn send(&mut self, buffer: MessageBuffer) -> Result<usize> {
+        let size = self.sock.send(buffer)?;
+        self.last_sent = Some(Instant::now());
+        Ok(size)
+    }
+
+    /// Send a message without blocking.
+    ///
+    /// See [`send`](#method.send).
+    pub fn send_nb(&mut self, buffer: MessageBuffer) -> Result<usize> {
+        let size = self.sock.send_nb(buffer)?;
+        self.last_sent = Some(Instant::now());
+        Ok(size)
+    }
+
+    /// Receive a message, skipping heartbeats.
+    ///
+    /// Heartbeats keep being sent while waiting.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout`: How long to wait for a message. `None` waits forever.
+    ///
+    /// # Returns
+    ///
+    /// `Err(error::TIMED_OUT)` if no message was received before the timeout.
+    pub fn recv(&mut self, timeout: Option<Duration>) -> Result<MessageBuffer> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            match self.recv_nb() {
+                Err(WOULD_BLOCK) => {},
+                other => return other
+            }
+            // Wake up in time for the next heartbeat
+            let mut wait = self.last_sent.map_or(Duration::from_secs(0), |t| {
+                (t + self.interval).saturating_duration_since(Instant::now())
+            });
+            if let Some(d) = deadline {
+                let left = d.saturating_duration_since(Instant::now());
+                if left == Duration::from_secs(0) {
+                    return Err(TIMED_OUT);
+                }
+                wait = wait.min(left);
+            }
+            poll_readable(self.sock.socket(), Some(Instant::now() + wait))?;
+        }
+    }
+
+    /// Receive a message without blocking, skipping heartbeats.
+    ///
+    /// This also calls [`tick`](#method.tick). If the tick fails after a message was received,
+    /// the message is returned, and the error is returned by the next call.
+    ///
+    /// Returns `Err(error::WOULD_BLOCK)` if no message is waiting.
+    pub fn recv_nb(&mut self) -> Result<MessageBuffer> {
+        if let Some(e) = self.deferred.take() {
+            return Err(e);
+        }
+        let result = loop {
+            match self.sock.recv_nb() {
+                Ok(msg) => {
+                    self.last_received = Some(Instant::now());
+                    self.alive = true;
+                    if &msg[..] != HEARTBEAT_FRAME {
+                        break Ok(msg);
+                    }
+                },
+                Err(e) => break Err(e)
+            }
+        };
+        match (result, self.tick()) {
+            (Ok(msg), Err(e)) => {
+                self.deferred = Some(e);
+                Ok(msg)
+            },
+            (_, Err(e)) => Err(e),
+            (result, Ok(())) => result
+        }
+    }
+}
@@ -20,13 +20,64 @@ extern crate libc;
 #[macro_use]
 extern crate bitflags;
 extern crate failure;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "bincode")]
+extern crate bincode;
+#[cfg(feature = "json")]
+extern crate serde_json;
+#[cfg(feature = "cbor")]
+extern crate ciborium;
+#[cfg(feature = "msgpack")]
+extern crate rmp_serde;
+#[cfg(feature = "mio")]
+extern crate mio;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "futures")]
+extern crate futures_core;
+#[cfg(feature = "futures")]
+extern crate futures_sink;
+#[cfg(feature = "futures")]
+extern crate async_io;
+#[cfg(feature = "derive")]
+extern crate nmsg_derive;
 
 pub mod alloc;
 pub mod error;
+pub mod pod;
 pub mod socket;
 pub mod protocol;
+pub mod poller;
+pub mod reactor;
+pub mod select;
+pub mod bridge;
+pub mod device;
+pub mod forwarder;
+pub mod fanin;
+pub mod router;
+pub mod reliable;
+pub mod discovery;
+pub mod heartbeat;
+pub mod lvc;
+pub mod sequence;
+pub mod busnode;
+pub mod pipeline;
+pub mod election;
+mod random;
+mod wire;
+#[cfg(feature = "serde")]
+pub mod codec;
+#[cfg(feature = "serde")]
+pub mod rpc;
+#[cfg(all(feature = "mio", unix))]
+pub mod mio_source;
+#[cfg(all(feature = "tokio", unix))]
+pub mod tokio_socket;
+#[cfg(all(feature = "futures", unix))]
+pub mod stream;
 
-pub use alloc::{MessageBuffer};
+pub use alloc::{MessageBuffer, MessageBufferPool, MessageWriter};
 pub use error::{Error, Result};
 pub use protocol::{
     Pub, Sub,
@@ -38,3 +89,7 @@ pub use protocol::{
 
     SPSocket, SPRecv, SPSend, Loopback
 };
+#[cfg(feature = "serde")]
+pub use codec::{Codec, Typed};
+#[cfg(feature = "derive")]
+pub use nmsg_derive::service;
@@ -0,0 +1,313 @@
+//! A last value cache for publish/subscribe.
+//!
+//! A [`Sub`](../protocol/struct.Sub.html) socket only receives messages published after it
+//! connected, so a late joiner doesn't know the current value of a topic until it is next
+//! published. An [`LvcProxy`](struct.LvcProxy.html) sits between the publishers and the
+//! subscribers: it republishes every message it receives upstream, remembers the latest message
+//! of each topic, and answers snapshot requests on a [`Rep`](../protocol/struct.Rep.html) socket.
+//!
+//! A late joiner connects its `Sub` socket to the proxy first, then requests a snapshot of the
+//! topics it is interested in, so that it sees every update after the snapshot.
+//!
+//! # Snapshots
+//!
+//! A snapshot request is the topic prefix to look up, with an empty request matching every
+//! topic. The reply is the cached messages of the matching topics, in topic order, each prefixed
+//! with its length as a big endian `u32`. [`parse_snapshot`](fn.parse_snapshot.html) splits a
+//! reply into messages.
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use alloc::MessageBuffer;
+use error::{Error, Result, INVALID, WOULD_BLOCK};
+use pod::{self, BigEndian};
+use poller::{Poller, Readiness};
+use protocol::{Pub, Rep, SPRecv, SPSend, Sub};
+
+/// The default maximum number of cached topics.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// The most messages forwarded per step, so that a busy upstream doesn't starve snapshot
+/// requests.
+const FORWARD_BATCH: usize = 64;
+
+/// Which topic an [`LvcProxy`](struct.LvcProxy.html) drops when its cache is full.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Eviction {
+    /// Drop the topic that was published least recently.
+    LeastRecentlyUpdated,
+    /// Drop the topic that was published or included in a snapshot least recently.
+    LeastRecentlyUsed,
+    /// Keep the cached topics, and don't cache new topics until there is room.
+    ///
+    /// Messages of uncached topics are still republished.
+    RejectNew
+}
+
+/// Counters for an [`LvcProxy`](struct.LvcProxy.html).
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct LvcStats {
+    /// The number of messages received upstream.
+    pub received: u64,
+    /// The number of messages republished downstream.
+    pub published: u64,
+    /// The number of snapshot requests answered.
+    pub snapshots: u64,
+    /// The number of topics dropped from the cache, or not cached, because it was full.
+    pub evicted: u64
+}
+
+/// Return a topic function for topics that end at the first `delimiter`.
+///
+/// A message without the delimiter is all topic.
+pub fn topic_until(delimiter: u8) -> impl Fn(&[u8]) -> usize {
+    move |msg| msg.iter().position(|&b| b == delimiter).unwrap_or(msg.len())
+}
+
+/// Split a snapshot reply into messages.
+///
+/// # Returns
+///
+/// `Err(error::INVALID)` if the reply is truncated.
+pub fn parse_snapshot(reply: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut messages = Vec::new();
+    let mut offset = 0;
+    while offset < reply.len() {
+        let len = pod::read::<BigEndian<u32>>(reply, offset)?.get() as usize;
+        offset += 4;
+        messages.push(reply.get(offset..offset + len).ok_or(INVALID)?);
+        offset += len;
+    }
+    Ok(messages)
+}
+
+struct Entry {
+    msg: Vec<u8>,
+    /// The position of the topic in the eviction order.
+    stamp: u64
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Token {
+    Upstream,
+    Snapshot
+}
+
+/// A proxy that republishes messages and caches the latest one of each topic.
+///
+/// `F` returns the length of the topic at the start of a message, see
+/// [`topic_until`](fn.topic_until.html).
+pub struct LvcProxy<F> {
+    upstream: Sub,
+    downstream: Pub,
+    snapshot: Rep,
+    topic: F,
+    cache: BTreeMap<Vec<u8>, Entry>,
+    order: BTreeMap<u64, Vec<u8>>,
+    clock: u64,
+    capacity: usize,
+    eviction: Eviction,
+    stats: LvcStats
+}
+
+impl<F: Fn(&[u8]) -> usize> LvcProxy<F> {
+    /// Create a proxy with an empty cache.
+    ///
+    /// `upstream` is subscribed to every topic. Subscribers filter on their own `Sub` sockets.
+    ///
+    /// # Arguments
+    ///
+    /// * `upstream`: The socket messages are received from publishers on.
+    /// * `downstream`: The socket messages are republished on.
+    /// * `snapshot`: The socket snapshot requests are answered on.
+    /// * `topic`: Returns the length of the topic of a message.
+    pub fn new(upstream: Sub, downstream: Pub, snapshot: Rep, topic: F) -> LvcProxy<F> {
+        upstream.subscribe(b"");
+        LvcProxy {
+            upstream,
+            downstream,
+            snapshot,
+            topic,
+            cache: BTreeMap::new(),
+            order: BTreeMap::new(),
+            clock: 0,
+            capacity: DEFAULT_CAPACITY,
+            eviction: Eviction::LeastRecentlyUpdated,
+            stats: LvcStats::default()
+        }
+    }
+
+    /// Get a reference to the upstream socket, for example to connect it.
+    #[inline]
+    pub fn upstream(&self) -> &Sub {
+        &self.upstream
+    }
+
+    /// Get a reference to the downstream socket, for example to bind it.
+    #[inline]
+    pub fn downstream(&self) -> &Pub {
+        &self.downstream
+    }
+
+    /// Get a reference to the snapshot socket, for example to bind it.
+    #[inline]
+    pub fn snapshot(&self) -> &Rep {
+        &self.snapshot
+    }
+
+    /// Set the maximum number of cached topics, and which topic to drop when there are more.
+    ///
+    /// Topics are dropped immediately if the cache is already larger.
+    pub fn set_capacity(&mut self, capacity: usize, eviction: Eviction) {
+        self.capacity = capacity;
+        self.eviction = eviction;
+        while self.cache.len() > self.capacity {
+            self.evict();
+        }
+    }
+
+    /// The number of cached topics.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Return true if no topics are cached.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    /// The latest message of a topic, if it is cached.
+    pub fn get(&self, topic: &[u8]) -> Option<&[u8]> {
+        self.cache.get(topic).map(|e| &e.msg[..])
+    }
+
+    /// The counters of the proxy.
+    #[inline]
+    pub fn stats(&self) -> LvcStats {
+        self.stats
+    }
+
+    /// Forward messages and answer snapshot requests until an error occurs.
+    ///
+    /// # Returns
+    ///
+    /// The error that stopped the proxy, for example `error::TERMINATING` once
+    /// [`Socket::terminate`](../socket/struct.Socket.html#method.terminate) is called.
+    pub fn run(&mut self) -> Error {
+        loop {
+            if let Err(e) = self.step(None) {
+                return e;
+            }
+        }
+    }
+
+    /// Wait until a message or snapshot request arrives, and handle what is waiting.
+    ///
+    /// At most 64 messages are forwarded per step, so a snapshot request waiting behind a busy
+    /// upstream is answered by the next step.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout`: How long to wait. `None` waits forever.
+    ///
+    /// # Returns
+    ///
+    /// The number of messages and requests handled, which is 0 if the timeout expired.
+    pub fn step(&mut self, timeout: Option<Duration>) -> Result<usize> {
+        let events = {
+            let mut poller = Poller::new();
+            poller.add(&self.upstream, Token::Upstream, Readiness::READABLE)?;
+            poller.add(&self.snapshot, Token::Snapshot, Readiness::READABLE)?;
+            poller.wait(timeout)?
+        };
+        let mut count = 0;
+        for (token, _) in events {
+            count += match token {
+                Token::Upstream => self.forward()?,
+                Token::Snapshot => self.answer()?
+            };
+        }
+        Ok(count)
+    }
+
+    fn forward(&mut self) -> Result<usize> {
+        let mut count = 0;
+        while count < FORWARD_BATCH {
+            let msg = match self.upstream.recv_nb() {
+                Ok(msg) => msg,
+                Err(WOULD_BLOCK) => break,
+                Err(e) => return Err(e)
+            };
+            self.stats.received += 1;
+            count += 1;
+            let topic_len = (self.topic)(&msg).min(msg.len());
+            self.update(&msg[..topic_len], &msg);
+            self.downstream.send(msg)?;
+            self.stats.published += 1;
+        }
+        Ok(count)
+    }
+
+    fn update(&mut self, topic: &[u8], msg: &[u8]) {
+        self.clock += 1;
+        let stamp = self.clock;
+        if let Some(entry) = self.cache.get_mut(topic) {
+            self.order.remove(&entry.stamp);
+            entry.msg = msg.to_vec();
+            entry.stamp = stamp;
+            self.order.insert(stamp, topic.to_vec());
+            return;
+        }
+        if self.cache.len() >= self.capacity {
+            if self.eviction == Eviction::RejectNew || self.cache.is_empty() {
+                self.stats.evicted += 1;
+                return;
+            }
+            self.evict();
+        }
+        self.cache.insert(topic.to_vec(), Entry { msg: msg.to_vec(), stamp });
+        self.order.insert(stamp, topic.to_vec());
+    }
+
+    /// Drop the topic at the front of the eviction order.
+    fn evict(&mut self) {
+        let oldest = self.order.keys().next().cloned();
+        if let Some(stamp) = oldest {
+            let topic = self.order.remove(&stamp).expect("eviction order");
+            self.cache.remove(&topic);
+            self.stats.evicted += 1;
+        }
+    }
+
+    fn answer(&mut self) -> Result<usize> {
+        let prefix = match self.snapshot.recv_nb() {
+            Ok(prefix) => prefix,
+            Err(WOULD_BLOCK) => return Ok(0),
+            Err(e) => return Err(e)
+        };
+        let mut reply = Vec::new();
+        let mut used = Vec::new();
+        for (topic, entry) in self.cache.range(prefix.to_vec()..) {
+            if !topic.starts_with(&prefix) {
+                break;
+            }
+            reply.extend_from_slice(pod::bytes_of(&BigEndian::new(entry.msg.len() as u32)));
+            reply.extend_from_slice(&entry.msg);
+            used.push(topic.clone());
+        }
+        if self.eviction == Eviction::LeastRecentlyUsed {
+            for topic in used {
+                self.clock += 1;
+                let entry = self.cache.get_mut(&topic).expect("cached topic");
+                self.order.remove(&entry.stamp);
+                entry.stamp = self.clock;
+                self.order.insert(self.clock, topic);
+            }
+        }
+        self.snapshot.send(MessageBuffer::from(reply))?;
+        self.stats.snapshots += 1;
+        Ok(1)
+    }
+}
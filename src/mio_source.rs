@@ -0,0 +1,126 @@
+//! Integration with the [mio](https://docs.rs/mio) event loop.
+//!
+//! With the `mio` feature, all of the [protocol](../protocol/index.html) sockets implement
+//! [`mio::event::Source`](https://docs.rs/mio/1/mio/event/trait.Source.html), so they can be
+//! registered with a `mio::Poll` directly.
+//!
+//! nanomsg sockets aren't file descriptors themselves. Instead, nanomsg provides a descriptor
+//! which becomes readable when a message can be received
+//! ([`recv_poll_fd`](../protocol/trait.SPRecv.html#method.recv_poll_fd)), and another that
+//! becomes readable when a message can be sent
+//! ([`send_poll_fd`](../protocol/trait.SPSend.html#method.send_poll_fd)). Registering a socket
+//! registers the descriptor that corresponds to each requested interest, always for readability.
+//!
+//! # Readiness
+//!
+//! Both descriptors are registered with the same token, and both signal readiness by becoming
+//! readable. This means that:
+//!
+//! * Events are always reported as readable, even for `Interest::WRITABLE`. If a socket is
+//!   registered for both interests, an event means that it may be possible to either receive or
+//!   send, and both should be attempted.
+//! * Registering for an interest that the socket doesn't support (for example
+//!   `Interest::WRITABLE` on a `Sub` socket) fails with `EINVAL`.
+//!
+//! # Edge vs. level
+//!
+//! The nanomsg descriptors are level triggered: they stay readable for as long as a message can
+//! be received (or sent). mio registers them edge triggered though, so an event is only
+//! delivered when the socket *becomes* ready. After an event, the socket must be drained with
+//! [`recv_nb`](../protocol/trait.SPRecv.html#method.recv_nb) (or
+//! [`send_nb`](../protocol/trait.SPSend.html#method.send_nb)) until it returns
+//! `Err(error::WOULD_BLOCK)`, otherwise no further events will be delivered for messages that
+//! are already queued.
+use std::io;
+
+use mio::{Interest, Registry, Token};
+use mio::event::Source;
+use mio::unix::SourceFd;
+
+use error::INVALID;
+use protocol::{
+    Pub, Sub,
+    Bus,
+    Req, Rep,
+    Push, Pull,
+    Surveyor, Respondent,
+    Pair,
+    SPRecv, SPSend
+};
+
+type PollFd = ::std::os::unix::io::RawFd;
+
+fn register(registry: &Registry,
+            token: Token,
+            interests: Interest,
+            recv_fd: Option<PollFd>,
+            send_fd: Option<PollFd>) -> io::Result<()> {
+    // Check both interests first, so that nothing is registered if either is unsupported
+    let recv_fd = if interests.is_readable() { Some(recv_fd.ok_or(INVALID)?) } else { None };
+    let send_fd = if interests.is_writable() { Some(send_fd.ok_or(INVALID)?) } else { None };
+    if let Some(fd) = recv_fd {
+        SourceFd(&fd).register(registry, token, Interest::READABLE)?;
+    }
+    if let Some(fd) = send_fd {
+        if let Err(e) = SourceFd(&fd).register(registry, token, Interest::READABLE) {
+            if let Some(fd) = recv_fd {
+                let _ = SourceFd(&fd).deregister(registry);
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+fn deregister(registry: &Registry, recv_fd: Option<PollFd>, send_fd: Option<PollFd>) -> io::Result<()> {
+    // Only the descriptors for the registered interests were added, so the others may be
+    // missing from the registry.
+    for fd in recv_fd.iter().chain(send_fd.iter()) {
+        match SourceFd(fd).deregister(registry) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            res => res?
+        }
+    }
+    Ok(())
+}
+
+macro_rules! poll_fd {
+    (yes, $sock:expr, $getter:ident) => { Some($sock.$getter()?) };
+    (no, $sock:expr, $getter:ident) => { None };
+}
+
+macro_rules! impl_source {
+    ($($name:ident : recv = $recv:ident, send = $send:ident;)*) => {$(
+        impl Source for $name {
+            fn register(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+                register(registry, token, interests,
+                         poll_fd!($recv, self, recv_poll_fd),
+                         poll_fd!($send, self, send_poll_fd))
+            }
+
+            fn reregister(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+                self.deregister(registry)?;
+                self.register(registry, token, interests)
+            }
+
+            fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+                deregister(registry,
+                           poll_fd!($recv, self, recv_poll_fd),
+                           poll_fd!($send, self, send_poll_fd))
+            }
+        }
+    )*}
+}
+
+impl_source!{
+    Pub: recv = no, send = yes;
+    Sub: recv = yes, send = no;
+    Bus: recv = yes, send = yes;
+    Req: recv = yes, send = yes;
+    Rep: recv = yes, send = yes;
+    Push: recv = no, send = yes;
+    Pull: recv = yes, send = no;
+    Surveyor: recv = yes, send = yes;
+    Respondent: recv = yes, send = yes;
+    Pair: recv = yes, send = yes;
+}
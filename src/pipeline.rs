@@ -0,0 +1,543 @@
+//! Scatter/gather jobs over push/pull sockets.
+//!
+//! This is the classic ventilator, workers and sink pipeline. A
+//! [`Ventilator`](struct.Ventilator.html) pushes the tasks of a job to the workers, each
+//! [`Worker`](struct.Worker.html) runs a function on the tasks it pulls and pushes the results
+//! to the [`Sink`](struct.Sink.html), and the sink gathers the results of the job, reporting the
+//! tasks whose results didn't arrive in time.
+//!
+//! [`LocalPipeline`](struct.LocalPipeline.html) runs the whole pipeline in one process, with a
+//! pool of worker threads connected over `inproc`.
+//!
+//! # Wire Format
+//!
+//! Tasks and results are `[batch: u64][index: u32][payload]`, with the integers in big endian.
+//! The batch identifies the job, and the index the task within the job.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use alloc::MessageBuffer;
+use error::{Error, Result, INVALID, WOULD_BLOCK};
+use poller::{poll_readable, poll_until};
+use pod::BigEndian;
+use protocol::{Pull, Push, SPRecv, SPSend, SPSocket};
+use random::random_id;
+use socket::Flags;
+
+/// The size of the task and result header.
+const HEADER_LEN: usize = 12;
+
+/// The default number of jobs a [`Sink`](struct.Sink.html) remembers, see
+/// [`Sink::set_window`](struct.Sink.html#method.set_window).
+pub const DEFAULT_WINDOW: usize = 1024;
+
+/// How often the threads of a local pipeline check whether they have been stopped.
+const STOP_INTERVAL: Duration = Duration::from_millis(100);
+
+fn encode(batch: u64, index: u32, payload: &[u8]) -> Result<MessageBuffer> {
+    let mut msg = MessageBuffer::new(HEADER_LEN + payload.len());
+    msg.write_pod(0, &BigEndian::new(batch))?;
+    msg.write_pod(8, &BigEndian::new(index))?;
+    msg[HEADER_LEN..].copy_from_slice(payload);
+    Ok(msg)
+}
+
+fn decode(msg: &MessageBuffer) -> Result<(u64, u32)> {
+    if msg.len() < HEADER_LEN {
+        return Err(INVALID);
+    }
+    Ok((msg.read_pod::<BigEndian<u64>>(0)?.get(), msg.read_pod::<BigEndian<u32>>(8)?.get()))
+}
+
+/// A job submitted by a [`Ventilator`](struct.Ventilator.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Job {
+    batch: u64,
+    len: u32
+}
+
+impl Job {
+    /// The batch id of the job.
+    #[inline]
+    pub fn batch(&self) -> u64 {
+        self.batch
+    }
+
+    /// The number of tasks in the job.
+    #[inline]
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Return true if the job has no tasks.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Pushes the tasks of jobs to workers.
+pub struct Ventilator {
+    sock: Push,
+    next_batch: u64
+}
+
+impl Ventilator {
+    /// Create a ventilator.
+    ///
+    /// Batch ids start from a random value, so that several ventilators can share a sink.
+    pub fn new(sock: Push) -> Ventilator {
+        Ventilator { sock, next_batch: random_id() }
+    }
+
+    /// Get a reference to the socket, for example to bind it.
+    #[inline]
+    pub fn get_ref(&self) -> &Push {
+        &self.sock
+    }
+
+    /// Push the tasks of a new job, blocking until every task has been sent.
+    ///
+    /// # Returns
+    ///
+    /// The job, to collect its results with [`Sink::collect`](struct.Sink.html#method.collect).
+    /// `Err(error::INVALID)` if there are more than `u32::MAX` tasks.
+    pub fn submit<I, T>(&mut self, tasks: I) -> Result<Job>
+        where I: IntoIterator<Item = T>, T: AsRef<[u8]>
+    {
+        let batch = self.batch();
+        let mut len = 0u32;
+        for task in tasks {
+            self.sock.send(encode(batch, len, task.as_ref())?)?;
+            len = len.checked_add(1).ok_or(INVALID)?;
+        }
+        Ok(Job { batch, len })
+    }
+
+    fn batch(&mut self) -> u64 {
+        let batch = self.next_batch;
+        self.next_batch = self.next_batch.wrapping_add(1);
+        batch
+    }
+}
+
+/// Runs a function on tasks, and pushes the results to a sink.
+pub struct Worker {
+    tasks: Pull,
+    results: Push
+}
+
+impl Worker {
+    /// Create a worker.
+    ///
+    /// # Arguments
+    ///
+    /// * `tasks`: The socket tasks are received from the ventilator on.
+    /// * `results`: The socket results are sent to the sink on.
+    pub fn new(tasks: Pull, results: Push) -> Worker {
+        Worker { tasks, results }
+    }
+
+    /// Get a reference to the task socket, for example to connect it.
+    #[inline]
+    pub fn tasks(&self) -> &Pull {
+        &self.tasks
+    }
+
+    /// Get a reference to the result socket, for example to connect it.
+    #[inline]
+    pub fn results(&self) -> &Push {
+        &self.results
+    }
+
+    /// Process tasks until an error occurs.
+    ///
+    /// Malformed tasks are skipped.
+    ///
+    /// # Returns
+    ///
+    /// The error that stopped the worker, for example `error::TERMINATING`.
+    pub fn run<F, R>(&self, mut f: F) -> Error
+        where F: FnMut(&[u8]) -> R, R: AsRef<[u8]>
+    {
+        loop {
+            match self.step(&mut f, None) {
+                Ok(_) | Err(INVALID) => {},
+                Err(e) => return e
+            }
+        }
+    }
+
+    /// Wait for a task, run `f` on it, and push the result.
+    ///
+    /// # Arguments
+    ///
+    /// * `f`: Computes the result of a task from its payload.
+    /// * `timeout`: How long to wait for a task. `None` waits forever.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the timeout expired before a task arrived.
+    /// `Err(error::INVALID)` if the message received isn't a task.
+    pub fn step<F, R>(&self, f: &mut F, timeout: Option<Duration>) -> Result<bool>
+        where F: FnMut(&[u8]) -> R, R: AsRef<[u8]>
+    {
+        if !poll_readable(self.tasks.socket(), timeout.map(|t| Instant::now() + t))? {
+            return Ok(false);
+        }
+        let task = match self.tasks.recv_nb() {
+            Ok(task) => task,
+            Err(WOULD_BLOCK) => return Ok(false),
+            Err(e) => return Err(e)
+        };
+        let (batch, index) = decode(&task)?;
+        let result = f(&task[HEADER_LEN..]);
+        self.results.send(encode(batch, index, result.as_ref())?)?;
+        Ok(true)
+    }
+}
+
+/// The results of a job gathered by a [`Sink`](struct.Sink.html).
+#[derive(Debug)]
+pub struct JobResults {
+    batch: u64,
+    results: Vec<Option<MessageBuffer>>
+}
+
+impl JobResults {
+    /// The batch id of the job.
+    #[inline]
+    pub fn batch(&self) -> u64 {
+        self.batch
+    }
+
+    /// The number of tasks in the job.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    /// Return true if the job has no tasks.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    /// Return true if the result of every task arrived.
+    pub fn is_complete(&self) -> bool {
+        self.results.iter().all(Option::is_some)
+    }
+
+    /// The result of a task, if it arrived.
+    pub fn get(&self, index: u32) -> Option<&[u8]> {
+        self.results.get(index as usize)?.as_ref().map(|msg| &msg[HEADER_LEN..])
+    }
+
+    /// The indices of the tasks whose results didn't arrive.
+    pub fn stragglers(&self) -> Vec<u32> {
+        self.results.iter()
+            .enumerate()
+            .filter(|&(_, result)| result.is_none())
+            .map(|(i, _)| i as u32)
+            .collect()
+    }
+
+    /// The results by task index, with `None` for the stragglers.
+    pub fn into_vec(self) -> Vec<Option<Vec<u8>>> {
+        self.results.into_iter()
+            .map(|result| result.map(|msg| msg[HEADER_LEN..].to_vec()))
+            .collect()
+    }
+}
+
+/// Gathers the results of jobs from workers.
+///
+/// Results of other jobs that arrive while collecting a job are kept until that job is
+/// collected. Results of a job that arrive after it was collected are dropped.
+///
+/// Both are bounded by a window of recent jobs: the results of the oldest other job are dropped
+/// once more jobs are pending, and late results of a job collected longer ago are kept as if it
+/// were pending.
+pub struct Sink {
+    sock: Pull,
+    pending: HashMap<u64, HashMap<u32, MessageBuffer>>,
+    pending_order: VecDeque<u64>,
+    collected: HashSet<u64>,
+    collected_order: VecDeque<u64>,
+    window: usize
+}
+
+impl Sink {
+    /// Create a sink.
+    pub fn new(sock: Pull) -> Sink {
+        Sink {
+            sock,
+            pending: HashMap::new(),
+            pending_order: VecDeque::new(),
+            collected: HashSet::new(),
+            collected_order: VecDeque::new(),
+            window: DEFAULT_WINDOW
+        }
+    }
+
+    /// Set how many pending and collected jobs are remembered.
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window;
+        self.trim();
+    }
+
+    /// Get a reference to the socket, for example to bind it.
+    #[inline]
+    pub fn get_ref(&self) -> &Pull {
+        &self.sock
+    }
+
+    /// Gather the results of a job.
+    ///
+    /// Returns once the result of every task has arrived, or the timeout expires. Duplicate
+    /// results of a task are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `job`: The job returned by [`Ventilator::submit`](struct.Ventilator.html#method.submit).
+    /// * `timeout`: How long to wait for the results. `None` waits forever.
+    ///
+    /// # Returns
+    ///
+    /// The results, with the missing ones reported by
+    /// [`JobResults::stragglers`](struct.JobResults.html#method.stragglers).
+    pub fn collect(&mut self, job: &Job, timeout: Option<Duration>) -> Result<JobResults> {
+        let mut results = (0..job.len).map(|_| None).collect::<Vec<Option<MessageBuffer>>>();
+        let mut remaining = job.len as usize;
+        for (index, msg) in self.take_pending(job.batch).unwrap_or_default() {
+            if let Some(slot) = results.get_mut(index as usize) {
+                *slot = Some(msg);
+                remaining -= 1;
+            }
+        }
+
+        let deadline = timeout.map(|t| Instant::now() + t);
+        while remaining > 0 {
+            if deadline.is_some_and(|d| d <= Instant::now()) {
+                break;
+            }
+            if !poll_readable(self.sock.socket(), deadline)? {
+                continue;
+            }
+            let msg = match self.sock.recv_nb() {
+                Ok(msg) => msg,
+                Err(WOULD_BLOCK) => continue,
+                Err(e) => return Err(e)
+            };
+            // Malformed results are ignored
+            let (batch, index) = match decode(&msg) {
+                Ok(header) => header,
+                Err(_) => continue
+            };
+            if batch != job.batch {
+                self.keep(batch, index, msg);
+                continue;
+            }
+            if let Some(slot) = results.get_mut(index as usize) {
+                if slot.is_none() {
+                    *slot = Some(msg);
+                    remaining -= 1;
+                }
+            }
+        }
+        self.mark_collected(job.batch);
+        Ok(JobResults { batch: job.batch, results })
+    }
+
+    /// Drop the results of a job that won't be collected.
+    pub fn discard(&mut self, job: &Job) {
+        self.take_pending(job.batch);
+        self.mark_collected(job.batch);
+    }
+
+    /// Keep the results that have already arrived, without blocking.
+    fn drain(&mut self) -> Result<()> {
+        loop {
+            let msg = match self.sock.recv_nb() {
+                Ok(msg) => msg,
+                Err(WOULD_BLOCK) => return Ok(()),
+                Err(e) => return Err(e)
+            };
+            if let Ok((batch, index)) = decode(&msg) {
+                self.keep(batch, index, msg);
+            }
+        }
+    }
+
+    fn keep(&mut self, batch: u64, index: u32, msg: MessageBuffer) {
+        if self.collected.contains(&batch) {
+            return;
+        }
+        if !self.pending.contains_key(&batch) {
+            self.pending_order.push_back(batch);
+        }
+        self.pending.entry(batch).or_default().insert(index, msg);
+        self.trim();
+    }
+
+    fn take_pending(&mut self, batch: u64) -> Option<HashMap<u32, MessageBuffer>> {
+        let results = self.pending.remove(&batch)?;
+        self.pending_order.retain(|&b| b != batch);
+        Some(results)
+    }
+
+    fn mark_collected(&mut self, batch: u64) {
+        if self.collected.insert(batch) {
+            self.collected_order.push_back(batch);
+            self.trim();
+        }
+    }
+
+    fn trim(&mut self) {
+        while self.pending_order.len() > self.window {
+            if let Some(batch) = self.pending_order.pop_front() {
+                self.pending.remove(&batch);
+            }
+        }
+        while self.collected_order.len() > self.window {
+            if let Some(batch) = self.collected_order.pop_front() {
+                self.collected.remove(&batch);
+            }
+        }
+    }
+}
+
+/// A pipeline running in one process, with a pool of worker threads.
+///
+/// Dropping the pipeline stops the worker threads, without waiting for them to exit.
+pub struct LocalPipeline {
+    ventilator: Ventilator,
+    sink: Sink,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<Result<()>>>
+}
+
+impl LocalPipeline {
+    /// Start a pipeline with `threads` worker threads running `f`.
+    ///
+    /// A worker thread that panics exits, and the results of its tasks are reported as
+    /// stragglers.
+    pub fn new<F, R>(threads: usize, f: F) -> Result<LocalPipeline>
+        where F: Fn(&[u8]) -> R + Send + Sync + 'static, R: AsRef<[u8]>
+    {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let tasks_addr = format!("inproc://nmsg-pipeline-{}-tasks", id);
+        let results_addr = format!("inproc://nmsg-pipeline-{}-results", id);
+
+        let tasks = Push::new()?;
+        tasks.bind(&tasks_addr)?;
+        let results = Pull::new()?;
+        results.bind(&results_addr)?;
+
+        let f = Arc::new(f);
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut handles = Vec::with_capacity(threads);
+        for _ in 0..threads.max(1) {
+            let worker = Worker::new(Pull::new()?, Push::new()?);
+            worker.tasks().connect(&tasks_addr)?;
+            worker.results().connect(&results_addr)?;
+            let f = f.clone();
+            let stop = stop.clone();
+            let handle = thread::Builder::new()
+                .name("nmsg-pipeline-worker".to_string())
+                .spawn(move || {
+                    let mut f = |task: &[u8]| f(task);
+                    while !stop.load(Ordering::SeqCst) {
+                        match worker.step(&mut f, Some(STOP_INTERVAL)) {
+                            Ok(_) | Err(INVALID) => {},
+                            Err(e) => return Err(e)
+                        }
+                    }
+                    Ok(())
+                })
+                .expect("failed to spawn pipeline worker thread");
+            handles.push(handle);
+        }
+
+        Ok(LocalPipeline {
+            ventilator: Ventilator::new(tasks),
+            sink: Sink::new(results),
+            stop,
+            threads: handles
+        })
+    }
+
+    /// The number of worker threads that are still running.
+    pub fn workers(&self) -> usize {
+        self.threads.iter().filter(|t| !t.is_finished()).count()
+    }
+
+    /// Push the tasks of a new job to the worker threads.
+    ///
+    /// See [`Ventilator::submit`](struct.Ventilator.html#method.submit).
+    pub fn submit<I, T>(&mut self, tasks: I) -> Result<Job>
+        where I: IntoIterator<Item = T>, T: AsRef<[u8]>
+    {
+        self.ventilator.submit(tasks)
+    }
+
+    /// Gather the results of a job.
+    ///
+    /// See [`Sink::collect`](struct.Sink.html#method.collect).
+    pub fn collect(&mut self, job: &Job, timeout: Option<Duration>) -> Result<JobResults> {
+        self.sink.collect(job, timeout)
+    }
+
+    /// Submit a job, and gather its results.
+    ///
+    /// Results are gathered while the tasks are pushed, so that a job with more tasks than the
+    /// socket buffers can hold doesn't block the workers. `timeout` covers both, and tasks that
+    /// couldn't be pushed before it expired are reported as stragglers.
+    pub fn run<I, T>(&mut self, tasks: I, timeout: Option<Duration>) -> Result<JobResults>
+        where I: IntoIterator<Item = T>, T: AsRef<[u8]>
+    {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let batch = self.ventilator.batch();
+        let mut len = 0u32;
+        let mut expired = false;
+        for task in tasks {
+            if !expired {
+                expired = !self.push(encode(batch, len, task.as_ref())?, deadline)?;
+            }
+            len = len.checked_add(1).ok_or(INVALID)?;
+        }
+        let timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        self.collect(&Job { batch, len }, timeout)
+    }
+
+    /// Push a task, gathering results while the workers are busy.
+    ///
+    /// Returns false if the deadline passed before the task could be pushed.
+    fn push(&mut self, mut msg: MessageBuffer, deadline: Option<Instant>) -> Result<bool> {
+        loop {
+            match self.ventilator.sock.socket().try_send(msg, Flags::DONTWAIT) {
+                Ok(_) => return Ok(true),
+                Err((WOULD_BLOCK, unsent)) => msg = unsent,
+                Err((e, _)) => return Err(e)
+            }
+            self.sink.drain()?;
+            let mut polls = [
+                self.ventilator.sock.socket().make_poll(false, true),
+                self.sink.sock.socket().make_poll(true, false)
+            ];
+            if poll_until(&mut polls, deadline)? == 0 && deadline.is_some_and(|d| d <= Instant::now()) {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+impl Drop for LocalPipeline {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
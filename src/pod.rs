@@ -0,0 +1,220 @@
+//! Safe access to plain-old-data in message buffers.
+//!
+//! This contains the [`Pod`](trait.Pod.html) marker trait for types that can be safely
+//! reinterpreted as bytes (and vice versa), and functions for reading and writing such types
+//! from byte slices with bounds and alignment checks.
+//!
+//! Since the in-memory representation of numbers depends on the platform, values that are sent
+//! to other processes should usually be wrapped in [`BigEndian`](struct.BigEndian.html) or
+//! [`LittleEndian`](struct.LittleEndian.html), which store the value with an explicit byte order.
+//!
+//! # See Also
+//!
+//! * [`MessageBuffer::from_pod`](../alloc/struct.MessageBuffer.html#method.from_pod)
+//! * [`MessageBuffer::read_pod`](../alloc/struct.MessageBuffer.html#method.read_pod)
+//! * [`MessageBuffer::as_pod_slice`](../alloc/struct.MessageBuffer.html#method.as_pod_slice)
+use std::fmt;
+use std::mem;
+use std::ops::Range;
+use std::ptr;
+use std::slice;
+
+use error::{Result, INVALID};
+
+/// Marker trait for plain-old-data types.
+///
+/// A type is plain-old-data if any sequence of bytes of the right length is a valid value of the
+/// type, and the type contains no pointers.
+///
+/// # Safety
+///
+/// Implementing this trait is only safe if the type:
+///
+/// * is `#[repr(C)]` or `#[repr(transparent)]` (or is a primitive),
+/// * has no padding bytes,
+/// * has no invalid bit patterns (so `bool`, `char`, enums and references are not allowed),
+/// * contains only fields that are themselves `Pod`.
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($t:ty),*) => {
+        $(unsafe impl Pod for $t {})*
+    }
+}
+
+impl_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+/// Trait for numeric types that can be converted between native and a fixed byte order.
+pub trait Endian: Pod {
+    /// Convert from native byte order to big endian.
+    fn native_to_big(self) -> Self;
+    /// Convert from big endian to native byte order.
+    fn big_to_native(self) -> Self;
+    /// Convert from native byte order to little endian.
+    fn native_to_little(self) -> Self;
+    /// Convert from little endian to native byte order.
+    fn little_to_native(self) -> Self;
+}
+
+macro_rules! impl_endian_int {
+    ($($t:ty),*) => {$(
+        impl Endian for $t {
+            #[inline]
+            fn native_to_big(self) -> $t { self.to_be() }
+            #[inline]
+            fn big_to_native(self) -> $t { <$t>::from_be(self) }
+            #[inline]
+            fn native_to_little(self) -> $t { self.to_le() }
+            #[inline]
+            fn little_to_native(self) -> $t { <$t>::from_le(self) }
+        }
+    )*}
+}
+
+impl_endian_int!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+macro_rules! impl_endian_float {
+    ($($t:ty),*) => {$(
+        impl Endian for $t {
+            #[inline]
+            fn native_to_big(self) -> $t { <$t>::from_bits(self.to_bits().to_be()) }
+            #[inline]
+            fn big_to_native(self) -> $t { <$t>::from_bits(self.to_bits().to_be()) }
+            #[inline]
+            fn native_to_little(self) -> $t { <$t>::from_bits(self.to_bits().to_le()) }
+            #[inline]
+            fn little_to_native(self) -> $t { <$t>::from_bits(self.to_bits().to_le()) }
+        }
+    )*}
+}
+
+impl_endian_float!(f32, f64);
+
+macro_rules! def_endian_wrapper {
+    ($(#[$attrs:meta])* struct $name:ident : $to:ident, $from:ident;) => {
+        $(#[$attrs])*
+        #[repr(transparent)]
+        #[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+        pub struct $name<T>(T);
+
+        impl<T: Endian> $name<T> {
+            /// Wrap a value in native byte order.
+            #[inline]
+            pub fn new(value: T) -> $name<T> {
+                $name(value.$to())
+            }
+
+            /// Get the value in native byte order.
+            #[inline]
+            pub fn get(self) -> T {
+                self.0.$from()
+            }
+
+            /// Set the value from a value in native byte order.
+            #[inline]
+            pub fn set(&mut self, value: T) {
+                self.0 = value.$to();
+            }
+        }
+
+        unsafe impl<T: Endian> Pod for $name<T> {}
+
+        impl<T: Endian> From<T> for $name<T> {
+            #[inline]
+            fn from(value: T) -> $name<T> {
+                $name::new(value)
+            }
+        }
+
+        impl<T: Endian + fmt::Debug> fmt::Debug for $name<T> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_tuple(stringify!($name)).field(&self.get()).finish()
+            }
+        }
+    }
+}
+
+def_endian_wrapper!{
+    /// A value stored in big endian (network) byte order.
+    ///
+    /// Note that the wrapper has the same alignment as `T`. Use
+    /// [`read`](fn.read.html) to read it from a position that may not be aligned.
+    struct BigEndian: native_to_big, big_to_native;
+}
+
+def_endian_wrapper!{
+    /// A value stored in little endian byte order.
+    ///
+    /// Note that the wrapper has the same alignment as `T`. Use
+    /// [`read`](fn.read.html) to read it from a position that may not be aligned.
+    struct LittleEndian: native_to_little, little_to_native;
+}
+
+/// View a plain-old-data value as bytes.
+#[inline]
+pub fn bytes_of<T: Pod>(value: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+}
+
+/// Read a value from `bytes` starting at `offset`.
+///
+/// The value doesn't need to be aligned.
+///
+/// # Returns
+///
+/// The value read, or `Err(error::INVALID)` if `bytes` is too short.
+pub fn read<T: Pod>(bytes: &[u8], offset: usize) -> Result<T> {
+    let src = checked_range::<T>(bytes.len(), offset)?;
+    Ok(unsafe { ptr::read_unaligned(bytes[src].as_ptr() as *const T) })
+}
+
+/// Write a value into `bytes` starting at `offset`.
+///
+/// The position doesn't need to be aligned.
+///
+/// # Returns
+///
+/// `Err(error::INVALID)` if `bytes` is too short.
+pub fn write<T: Pod>(bytes: &mut [u8], offset: usize, value: &T) -> Result<()> {
+    let dest = checked_range::<T>(bytes.len(), offset)?;
+    bytes[dest].copy_from_slice(bytes_of(value));
+    Ok(())
+}
+
+/// View `bytes` as a slice of plain-old-data values.
+///
+/// # Returns
+///
+/// The slice, or `Err(error::INVALID)` if `bytes` isn't aligned for `T`, its length isn't a
+/// multiple of the size of `T`, or `T` is zero-sized.
+pub fn cast_slice<T: Pod>(bytes: &[u8]) -> Result<&[T]> {
+    let len = checked_len::<T>(bytes.as_ptr(), bytes.len())?;
+    Ok(unsafe { slice::from_raw_parts(bytes.as_ptr() as *const T, len) })
+}
+
+/// View `bytes` as a mutable slice of plain-old-data values.
+///
+/// See [`cast_slice`](fn.cast_slice.html).
+pub fn cast_slice_mut<T: Pod>(bytes: &mut [u8]) -> Result<&mut [T]> {
+    let len = checked_len::<T>(bytes.as_ptr(), bytes.len())?;
+    Ok(unsafe { slice::from_raw_parts_mut(bytes.as_mut_ptr() as *mut T, len) })
+}
+
+fn checked_range<T>(len: usize, offset: usize) -> Result<Range<usize>> {
+    match offset.checked_add(mem::size_of::<T>()) {
+        Some(end) if end <= len => Ok(offset..end),
+        _ => Err(INVALID)
+    }
+}
+
+// usize::is_multiple_of would need Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
+fn checked_len<T>(ptr: *const u8, len: usize) -> Result<usize> {
+    let size = mem::size_of::<T>();
+    if size == 0 || len % size != 0 || ptr as usize % mem::align_of::<T>() != 0 {
+        return Err(INVALID);
+    }
+    Ok(len / size)
+}
@@ -0,0 +1,291 @@
+//! Polling multiple sockets for readiness.
+//!
+//! [`Socket::poll`](../socket/struct.Socket.html#method.poll) works on a bare slice of
+//! [`Poll`](../socket/struct.Poll.html) objects, so the caller has to keep track of which entry
+//! belongs to which socket. A [`Poller`](struct.Poller.html) keeps a set of registered sockets,
+//! each with a user supplied token, and reports readiness by token.
+use std::time::{Duration, Instant};
+use std::thread;
+use std::vec;
+#[cfg(unix)]
+use std::os::unix::io::RawFd as PollFd;
+#[cfg(windows)]
+use std::os::windows::io::RawSocket as PollFd;
+
+use libc::c_int;
+use nanomsg_sys::{NN_SOL_SOCKET, NN_RCVFD, NN_SNDFD};
+
+use error::{Result, INTERRUPT, INVALID, NO_OPTION};
+use protocol::SPSocket;
+use socket::{Poll, Socket};
+
+bitflags!{
+    /// The readiness of a socket.
+    ///
+    /// This is used both for the interest a socket is registered with, and for the readiness
+    /// reported by [`Poller::wait`](struct.Poller.html#method.wait).
+    pub struct Readiness: u8 {
+        /// A message can be received without blocking.
+        const READABLE = 0b01;
+        /// A message can be sent without blocking.
+        const WRITABLE = 0b10;
+    }
+}
+
+impl Readiness {
+    /// Return true if a message can be received.
+    #[inline]
+    pub fn is_readable(&self) -> bool {
+        self.contains(Readiness::READABLE)
+    }
+
+    /// Return true if a message can be sent.
+    #[inline]
+    pub fn is_writable(&self) -> bool {
+        self.contains(Readiness::WRITABLE)
+    }
+}
+
+/// A set of sockets to poll for readiness.
+///
+/// Sockets are registered with a token of type `T`, and an interest. The poller borrows the
+/// registered sockets, so they are guaranteed to stay open while registered.
+///
+/// # See Also
+///
+/// * [nn_poll(3)](http://nanomsg.org/v1.1.2/nn_poll.html)
+pub struct Poller<'a, T> {
+    polls: Vec<Poll>,
+    sockets: Vec<&'a Socket>,
+    tokens: Vec<T>
+}
+
+impl<'a, T: Clone + PartialEq> Poller<'a, T> {
+    /// Create a new, empty poller.
+    pub fn new() -> Poller<'a, T> {
+        Poller {
+            polls: Vec::new(),
+            sockets: Vec::new(),
+            tokens: Vec::new()
+        }
+    }
+
+    /// Register a socket.
+    ///
+    /// # Returns
+    ///
+    /// `Err(error::NO_OPTION)` if the socket doesn't support the interest (for example
+    /// `Readiness::WRITABLE` for a `Sub` socket), or `Err(error::INVALID)` if the interest is
+    /// empty.
+    pub fn add<S: SPSocket>(&mut self, sock: &'a S, token: T, interest: Readiness) -> Result<()> {
+        let poll = make_poll(sock.socket(), interest)?;
+        self.polls.push(poll);
+        self.sockets.push(sock.socket());
+        self.tokens.push(token);
+        Ok(())
+    }
+
+    /// Change the interest of the sockets registered with `token`.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(false)` if no socket was registered with `token`.
+    pub fn modify(&mut self, token: &T, interest: Readiness) -> Result<bool> {
+        let mut found = false;
+        for ((poll, sock), t) in self.polls.iter_mut().zip(&self.sockets).zip(&self.tokens) {
+            if t == token {
+                *poll = make_poll(sock, interest)?;
+                found = true;
+            }
+        }
+        Ok(found)
+    }
+
+    /// Deregister the sockets registered with `token`.
+    ///
+    /// # Returns
+    ///
+    /// `false` if no socket was registered with `token`.
+    pub fn remove(&mut self, token: &T) -> bool {
+        let len = self.tokens.len();
+        let mut i = 0;
+        while i < self.tokens.len() {
+            if self.tokens[i] == *token {
+                self.tokens.swap_remove(i);
+                self.polls.swap_remove(i);
+                self.sockets.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        self.tokens.len() != len
+    }
+
+    /// The number of registered sockets.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Return true if no sockets are registered.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Wait until at least one registered socket is ready, or the timeout expires.
+    ///
+    /// If the wait is interrupted by a signal, it is resumed with the remaining timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout`: How long to wait for a socket to become ready. `None` waits forever.
+    ///
+    /// # Returns
+    ///
+    /// An iterator of the tokens of the ready sockets, along with their readiness. It doesn't
+    /// borrow the poller, so sockets can be added or removed while handling the events. If the
+    /// timeout expires the iterator is empty.
+    ///
+    /// `Err(error::INVALID)` is returned if no sockets are registered and `timeout` is `None`,
+    /// since that would block forever.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<Events<T>> {
+        if self.polls.is_empty() {
+            // nn_poll doesn't accept an empty set
+            return match timeout {
+                Some(t) => {
+                    thread::sleep(t);
+                    Ok(Events { inner: Vec::new().into_iter() })
+                },
+                None => Err(INVALID)
+            };
+        }
+        poll_until(&mut self.polls, timeout.map(|t| Instant::now() + t))?;
+        let events = self.polls.iter().zip(&self.tokens)
+            .filter_map(|(poll, token)| {
+                let mut ready = Readiness::empty();
+                if poll.can_receive() {
+                    ready |= Readiness::READABLE;
+                }
+                if poll.can_send() {
+                    ready |= Readiness::WRITABLE;
+                }
+                if ready.is_empty() {
+                    None
+                } else {
+                    Some((token.clone(), ready))
+                }
+            })
+            .collect::<Vec<_>>();
+        Ok(Events { inner: events.into_iter() })
+    }
+
+    /// Wait like [`wait`](#method.wait), also waiting for a message on `sock`, which isn't
+    /// registered.
+    ///
+    /// # Returns
+    ///
+    /// The ready registered sockets, and whether `sock` has a message.
+    pub(crate) fn wait_with(&mut self, sock: &Socket, timeout: Option<Duration>) -> Result<(Events<T>, bool)> {
+        // The extra entry has no token, so it isn't reported in the events
+        self.polls.push(sock.make_poll(true, false));
+        let res = self.wait(timeout);
+        let poll = self.polls.pop().expect("extra poll");
+        Ok((res?, poll.can_receive()))
+    }
+}
+
+impl<'a, T: Clone + PartialEq> Default for Poller<'a, T> {
+    fn default() -> Poller<'a, T> {
+        Poller::new()
+    }
+}
+
+/// Iterator over the ready sockets returned by [`Poller::wait`](struct.Poller.html#method.wait).
+pub struct Events<T> {
+    inner: vec::IntoIter<(T, Readiness)>
+}
+
+impl<T> Iterator for Events<T> {
+    type Item = (T, Readiness);
+
+    #[inline]
+    fn next(&mut self) -> Option<(T, Readiness)> {
+        self.inner.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T> ExactSizeIterator for Events<T> {}
+
+fn make_poll(sock: &Socket, interest: Readiness) -> Result<Poll> {
+    if interest.is_empty() {
+        return Err(INVALID);
+    }
+    // nn_poll fails if any socket doesn't support its interest, so check up front.
+    if interest.is_readable() && !has_poll_fd(sock, NN_RCVFD)? {
+        return Err(NO_OPTION);
+    }
+    if interest.is_writable() && !has_poll_fd(sock, NN_SNDFD)? {
+        return Err(NO_OPTION);
+    }
+    Ok(sock.make_poll(interest.is_readable(), interest.is_writable()))
+}
+
+/// Return true if the socket has the poll descriptor `option` (`NN_RCVFD` or `NN_SNDFD`).
+pub(crate) fn has_poll_fd(sock: &Socket, option: c_int) -> Result<bool> {
+    match unsafe { sock.get_option::<PollFd>(NN_SOL_SOCKET, option) } {
+        Ok(_) => Ok(true),
+        Err(NO_OPTION) => Ok(false),
+        Err(e) => Err(e)
+    }
+}
+
+/// Poll until a socket is ready or `deadline` passes, retrying when interrupted by a signal.
+///
+/// # Arguments
+///
+/// * `polls`: The sockets and readiness to wait for.
+/// * `deadline`: When to stop waiting. `None` waits forever.
+///
+/// # Returns
+///
+/// The number of ready sockets, which is 0 if the deadline passed.
+pub(crate) fn poll_until(polls: &mut [Poll], deadline: Option<Instant>) -> Result<usize> {
+    loop {
+        let millis = match deadline {
+            Some(d) => timeout_millis(d.saturating_duration_since(Instant::now())),
+            None => -1
+        };
+        match Socket::poll(polls, millis) {
+            Err(INTERRUPT) => continue,
+            res => return res
+        }
+    }
+}
+
+/// Wait until `sock` has a message or `deadline` passes, retrying when interrupted by a signal.
+///
+/// # Returns
+///
+/// `false` if the deadline passed.
+pub(crate) fn poll_readable(sock: &Socket, deadline: Option<Instant>) -> Result<bool> {
+    let mut polls = [sock.make_poll(true, false)];
+    Ok(poll_until(&mut polls, deadline)? > 0)
+}
+
+/// Convert a timeout to milliseconds for nn_poll, rounding up.
+pub(crate) fn timeout_millis(timeout: Duration) -> i32 {
+    let millis = timeout.as_secs()
+        .saturating_mul(1000)
+        .saturating_add(u64::from(timeout.subsec_nanos()).div_ceil(1_000_000));
+    if millis > i32::MAX as u64 {
+        i32::MAX
+    } else {
+        millis as i32
+    }
+}
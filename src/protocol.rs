@@ -64,6 +64,11 @@ pub trait SPSocket {
     /// In most cases this shouldn't be used by user code.
     fn socket(&self) -> &Socket;
 
+    /// Convert into the internal socket.
+    ///
+    /// In most cases this shouldn't be used by user code.
+    fn into_socket(self) -> Socket where Self: Sized;
+
     /// Get the underlying protocol.
     ///
     /// Return a [`Protocol`](../socket/enum.Protocol.html) for the socket.
@@ -408,6 +413,10 @@ macro_rules! def_protocols {
                 fn socket(&self) -> &Socket {
                     &self.sock
                 }
+
+                fn into_socket(self) -> Socket {
+                    self.sock
+                }
             }
 
             $(impl $extra for $name { })+
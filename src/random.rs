@@ -0,0 +1,14 @@
+//! Random ids for nodes, publishers and batches.
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::process;
+
+/// A random 64-bit id.
+///
+/// This uses the random keys of `RandomState`, mixed with the process id so that processes
+/// started at the same time still get different ids.
+pub(crate) fn random_id() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(process::id());
+    hasher.finish()
+}
@@ -0,0 +1,299 @@
+//! A single threaded, callback driven event loop.
+//!
+//! A [`Reactor`](struct.Reactor.html) serves many sockets from one thread. Handlers are
+//! registered per socket with [`on_recv`](struct.Reactor.html#method.on_recv), and called with
+//! every message received on it. Timers can be registered with
+//! [`after`](struct.Reactor.html#method.after) and [`every`](struct.Reactor.html#method.every).
+//!
+//! Other threads interact with a running reactor through a
+//! [`ReactorHandle`](struct.ReactorHandle.html), which can stop it, or schedule work to run on
+//! the reactor thread.
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use alloc::MessageBuffer;
+use error::{Result, TERMINATING, WOULD_BLOCK};
+use poller::{Poller, Readiness};
+use protocol::{Pair, SPRecv, SPSend, SPSocket};
+use socket::{Flags, RawFd, Socket};
+
+/// Identifies a timer registered with a [`Reactor`](struct.Reactor.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+struct Handler<'a> {
+    sock: &'a Socket,
+    callback: Box<dyn FnMut(MessageBuffer) + 'a>
+}
+
+struct Timer<'a> {
+    interval: Option<Duration>,
+    callback: Box<dyn FnMut() + 'a>
+}
+
+type Task = Box<dyn FnOnce() + Send>;
+
+struct Shared {
+    stopped: AtomicBool,
+    tasks: Mutex<VecDeque<Task>>,
+    waker: Pair
+}
+
+impl Shared {
+    fn wake(&self) {
+        // If a wake up is already queued, another one isn't needed.
+        let _ = self.waker.send_buf_nb(&[]);
+    }
+}
+
+/// A handle to a [`Reactor`](struct.Reactor.html), which can be sent to other threads.
+#[derive(Clone)]
+pub struct ReactorHandle {
+    shared: Arc<Shared>
+}
+
+impl ReactorHandle {
+    /// Stop the reactor.
+    ///
+    /// [`run`](struct.Reactor.html#method.run) returns once the current iteration completes. If
+    /// the reactor isn't running, the next call to `run` returns immediately.
+    pub fn stop(&self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        self.shared.wake();
+    }
+
+    /// Schedule `task` to run on the reactor thread.
+    ///
+    /// Tasks run in the order they were scheduled, during the next iteration of the reactor.
+    pub fn schedule<F: FnOnce() + Send + 'static>(&self, task: F) {
+        self.shared.tasks.lock().unwrap().push_back(Box::new(task));
+        self.shared.wake();
+    }
+}
+
+/// A single threaded event loop.
+///
+/// The reactor borrows the sockets that handlers are registered for, so they stay open for as
+/// long as the reactor exists.
+pub struct Reactor<'a> {
+    poller: Poller<'a, RawFd>,
+    handlers: HashMap<RawFd, Handler<'a>>,
+    timers: HashMap<TimerId, Timer<'a>>,
+    deadlines: BinaryHeap<Reverse<(Instant, TimerId)>>,
+    next_timer: u64,
+    wake: Pair,
+    shared: Arc<Shared>
+}
+
+impl<'a> Reactor<'a> {
+    /// Create a new reactor.
+    ///
+    /// This creates a pair of inproc sockets, used to wake the reactor from a
+    /// [`ReactorHandle`](struct.ReactorHandle.html).
+    pub fn new() -> Result<Reactor<'a>> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let addr = format!("inproc://nmsg-reactor-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        let wake = Pair::new()?;
+        wake.bind(&addr)?;
+        let waker = Pair::new()?;
+        waker.connect(&addr)?;
+
+        Ok(Reactor {
+            poller: Poller::new(),
+            handlers: HashMap::new(),
+            timers: HashMap::new(),
+            deadlines: BinaryHeap::new(),
+            next_timer: 0,
+            wake,
+            shared: Arc::new(Shared {
+                stopped: AtomicBool::new(false),
+                tasks: Mutex::new(VecDeque::new()),
+                waker
+            })
+        })
+    }
+
+    /// Get a handle that can be used to stop the reactor, or schedule work on it.
+    pub fn handle(&self) -> ReactorHandle {
+        ReactorHandle { shared: self.shared.clone() }
+    }
+
+    /// Call `handler` with every message received on `sock`.
+    ///
+    /// If a handler is already registered for `sock`, it is replaced.
+    pub fn on_recv<S, F>(&mut self, sock: &'a S, handler: F) -> Result<()>
+        where S: SPRecv, F: FnMut(MessageBuffer) + 'a
+    {
+        let fd = unsafe { sock.socket().as_raw_fd() };
+        if !self.handlers.contains_key(&fd) {
+            self.poller.add(sock, fd, Readiness::READABLE)?;
+        }
+        self.handlers.insert(fd, Handler { sock: sock.socket(), callback: Box::new(handler) });
+        Ok(())
+    }
+
+    /// Remove the handler registered for `sock`.
+    ///
+    /// # Returns
+    ///
+    /// `false` if no handler was registered for `sock`.
+    pub fn remove<S: SPSocket>(&mut self, sock: &S) -> bool {
+        let fd = unsafe { sock.socket().as_raw_fd() };
+        self.poller.remove(&fd);
+        self.handlers.remove(&fd).is_some()
+    }
+
+    /// Call `callback` once, after `delay`.
+    pub fn after<F: FnOnce() + 'a>(&mut self, delay: Duration, callback: F) -> TimerId {
+        let mut callback = Some(callback);
+        self.add_timer(delay, None, Box::new(move || {
+            if let Some(callback) = callback.take() {
+                callback()
+            }
+        }))
+    }
+
+    /// Call `callback` every `interval`, starting one interval from now.
+    ///
+    /// If the reactor falls behind, missed calls are skipped rather than made in a burst. A timer
+    /// is called at most once per iteration of the reactor, so a zero `interval` calls `callback`
+    /// once per iteration.
+    pub fn every<F: FnMut() + 'a>(&mut self, interval: Duration, callback: F) -> TimerId {
+        self.add_timer(interval, Some(interval), Box::new(callback))
+    }
+
+    /// Cancel a timer.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the timer has already fired, or was already cancelled.
+    pub fn cancel(&mut self, timer: TimerId) -> bool {
+        // The deadline is left in the heap, and skipped when it expires.
+        self.timers.remove(&timer).is_some()
+    }
+
+    fn add_timer(&mut self, delay: Duration, interval: Option<Duration>, callback: Box<dyn FnMut() + 'a>) -> TimerId {
+        let id = TimerId(self.next_timer);
+        self.next_timer += 1;
+        self.timers.insert(id, Timer { interval, callback });
+        self.deadlines.push(Reverse((Instant::now() + delay, id)));
+        id
+    }
+
+    /// Run the reactor until it is stopped with [`ReactorHandle::stop`](struct.ReactorHandle.html#method.stop),
+    /// or nanomsg is terminated.
+    ///
+    /// # Returns
+    ///
+    /// An error if polling or receiving on one of the sockets fails.
+    pub fn run(&mut self) -> Result<()> {
+        let res = self.run_until_stopped();
+        self.shared.stopped.store(false, Ordering::SeqCst);
+        res
+    }
+
+    fn run_until_stopped(&mut self) -> Result<()> {
+        while !self.shared.stopped.load(Ordering::SeqCst) {
+            match self.run_once(None) {
+                Err(TERMINATING) => return Ok(()),
+                res => res?
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a single iteration of the reactor.
+    ///
+    /// This waits until a socket is ready, a timer expires, work is scheduled, or `timeout`
+    /// passes, and then dispatches the ready sockets, expired timers and scheduled tasks.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout`: The longest time to wait. `None` waits until there is something to do.
+    pub fn run_once(&mut self, timeout: Option<Duration>) -> Result<()> {
+        let now = Instant::now();
+        let timeout = match self.deadlines.peek() {
+            Some(&Reverse((deadline, _))) => {
+                let until = deadline.saturating_duration_since(now);
+                Some(timeout.map_or(until, |t| t.min(until)))
+            },
+            None => timeout
+        };
+
+        let (events, woken) = self.poller.wait_with(self.wake.socket(), timeout)?;
+        if woken {
+            self.drain_wake()?;
+        }
+        for (fd, _) in events {
+            // Only one message is received per socket and iteration, so that a busy socket
+            // doesn't starve the others.
+            if let Some(handler) = self.handlers.get_mut(&fd) {
+                match handler.sock.recv(Flags::DONTWAIT) {
+                    Ok(msg) => (handler.callback)(msg),
+                    Err(WOULD_BLOCK) => {},
+                    Err(e) => return Err(e)
+                }
+            }
+        }
+
+        self.run_tasks();
+        self.run_timers();
+        Ok(())
+    }
+
+    fn drain_wake(&self) -> Result<()> {
+        let mut buf = [0u8; 1];
+        loop {
+            match self.wake.recv_buf_nb(&mut buf) {
+                Ok(_) => {},
+                Err(WOULD_BLOCK) => return Ok(()),
+                Err(e) => return Err(e)
+            }
+        }
+    }
+
+    fn run_tasks(&self) {
+        // Take the tasks first, so that tasks can schedule more work without deadlocking.
+        let tasks = mem::take(&mut *self.shared.tasks.lock().unwrap());
+        for task in tasks {
+            task();
+        }
+    }
+
+    fn run_timers(&mut self) {
+        let now = Instant::now();
+        // Repeating timers are rescheduled after the loop, so each timer fires at most once per
+        // call, even with a zero interval.
+        let mut rescheduled = Vec::new();
+        while let Some(&Reverse((deadline, id))) = self.deadlines.peek() {
+            if deadline > now {
+                break;
+            }
+            self.deadlines.pop();
+            let repeat = match self.timers.get_mut(&id) {
+                Some(timer) => {
+                    (timer.callback)();
+                    timer.interval
+                },
+                None => continue
+            };
+            match repeat {
+                Some(interval) => {
+                    let mut next = deadline + interval;
+                    if next <= now {
+                        next = now + interval;
+                    }
+                    rescheduled.push(Reverse((next, id)));
+                },
+                None => {
+                    self.timers.remove(&id);
+                }
+            }
+        }
+        self.deadlines.extend(rescheduled);
+    }
+}
@@ -0,0 +1,295 @@
+//! At-least-once task distribution over push/pull sockets.
+//!
+//! [`Push`](../protocol/struct.Push.html) and [`Pull`](../protocol/struct.Pull.html) don't
+//! acknowledge messages, so a task is lost if the worker that received it dies. A
+//! [`ReliableQueue`](struct.ReliableQueue.html) keeps every task it sends until a
+//! [`ReliableWorker`](struct.ReliableWorker.html) acknowledges it over a second push/pull
+//! channel. Tasks that aren't acknowledged within the visibility timeout are sent again, until
+//! they have been attempted too often, at which point they are handed to a dead letter callback.
+//!
+//! Since a task can be redelivered after a slow worker has already started on it, workers may
+//! see the same task more than once and should handle tasks idempotently.
+//!
+//! A task message is `[id: u64][attempt: u32][payload]`, and an acknowledgement is `[id: u64]`,
+//! with the integers in big endian.
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::{Duration, Instant};
+
+use alloc::MessageBuffer;
+use error::{Result, INVALID, WOULD_BLOCK};
+use poller::poll_readable;
+use pod::BigEndian;
+use protocol::{Pull, Push, SPRecv, SPSend, SPSocket};
+
+/// The size of the task header.
+const HEADER_LEN: usize = 12;
+
+/// The default visibility timeout.
+pub const DEFAULT_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The default maximum number of attempts.
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+type DeadLetter = Box<dyn FnMut(u64, Vec<u8>)>;
+
+struct Pending {
+    payload: Vec<u8>,
+    attempts: u32,
+    deadline: Instant
+}
+
+/// Sends tasks to workers, and redelivers them until they are acknowledged.
+pub struct ReliableQueue {
+    tasks: Push,
+    acks: Pull,
+    pending: HashMap<u64, Pending>,
+    deadlines: BinaryHeap<Reverse<(Instant, u64)>>,
+    next_id: u64,
+    visibility_timeout: Duration,
+    max_attempts: u32,
+    dead_letter: Option<DeadLetter>
+}
+
+impl ReliableQueue {
+    /// Create a queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `tasks`: The socket tasks are sent to workers on.
+    /// * `acks`: The socket acknowledgements are received from workers on.
+    pub fn new(tasks: Push, acks: Pull) -> ReliableQueue {
+        ReliableQueue {
+            tasks,
+            acks,
+            pending: HashMap::new(),
+            deadlines: BinaryHeap::new(),
+            next_id: 1,
+            visibility_timeout: DEFAULT_VISIBILITY_TIMEOUT,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            dead_letter: None
+        }
+    }
+
+    /// Get a reference to the task socket, for example to bind it.
+    #[inline]
+    pub fn tasks(&self) -> &Push {
+        &self.tasks
+    }
+
+    /// Get a reference to the acknowledgement socket, for example to bind it.
+    #[inline]
+    pub fn acks(&self) -> &Pull {
+        &self.acks
+    }
+
+    /// Set how long a worker has to acknowledge a task before it is sent again.
+    ///
+    /// This applies to tasks sent after the call.
+    pub fn set_visibility_timeout(&mut self, timeout: Duration) {
+        self.visibility_timeout = timeout;
+    }
+
+    /// Set how many times a task is sent before it is given up on.
+    pub fn set_max_attempts(&mut self, attempts: u32) {
+        self.max_attempts = attempts.max(1);
+    }
+
+    /// Set the callback for tasks that were given up on.
+    ///
+    /// The callback is called with the id and payload of the task. Without a callback, such
+    /// tasks are dropped.
+    pub fn on_dead_letter<F: FnMut(u64, Vec<u8>) + 'static>(&mut self, callback: F) {
+        self.dead_letter = Some(Box::new(callback));
+    }
+
+    /// The number of tasks that haven't been acknowledged yet.
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Send a new task.
+    ///
+    /// Blocks until the task can be sent.
+    ///
+    /// # Returns
+    ///
+    /// The id of the task.
+    pub fn submit(&mut self, payload: &[u8]) -> Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(id, 1, payload)?;
+        let deadline = Instant::now() + self.visibility_timeout;
+        self.pending.insert(id, Pending { payload: payload.to_vec(), attempts: 1, deadline });
+        self.deadlines.push(Reverse((deadline, id)));
+        Ok(id)
+    }
+
+    fn send(&self, id: u64, attempt: u32, payload: &[u8]) -> Result<usize> {
+        let mut msg = MessageBuffer::new(HEADER_LEN + payload.len());
+        msg.write_pod(0, &BigEndian::new(id))?;
+        msg.write_pod(8, &BigEndian::new(attempt))?;
+        msg[HEADER_LEN..].copy_from_slice(payload);
+        self.tasks.send(msg)
+    }
+
+    /// Process acknowledgements and redeliver expired tasks.
+    ///
+    /// This should be called regularly. It waits for acknowledgements until `timeout` passes or
+    /// the next task expires, whichever is first.
+    ///
+    /// # Returns
+    ///
+    /// The number of tasks acknowledged.
+    pub fn poll(&mut self, timeout: Option<Duration>) -> Result<usize> {
+        let now = Instant::now();
+        let wait = match self.deadlines.peek() {
+            Some(&Reverse((deadline, _))) => {
+                let until = deadline.saturating_duration_since(now);
+                Some(timeout.map_or(until, |t| t.min(until)))
+            },
+            None => timeout
+        };
+        let mut acked = 0;
+        if poll_readable(self.acks.socket(), wait.map(|t| now + t))? {
+            loop {
+                match self.acks.recv_nb() {
+                    Ok(ack) => {
+                        // Malformed acknowledgements are ignored
+                        if let Ok(id) = ack.read_pod::<BigEndian<u64>>(0) {
+                            if self.pending.remove(&id.get()).is_some() {
+                                acked += 1;
+                            }
+                        }
+                    },
+                    Err(WOULD_BLOCK) => break,
+                    Err(e) => return Err(e)
+                }
+            }
+        }
+        self.redeliver()?;
+        Ok(acked)
+    }
+
+    fn redeliver(&mut self) -> Result<()> {
+        let now = Instant::now();
+        while let Some(&Reverse((deadline, id))) = self.deadlines.peek() {
+            if deadline > now {
+                break;
+            }
+            self.deadlines.pop();
+            // Skip tasks that were acknowledged, or whose deadline has moved
+            let expired = self.pending.get(&id).is_some_and(|p| p.deadline == deadline);
+            if !expired {
+                continue;
+            }
+            let attempts = self.pending[&id].attempts;
+            if attempts >= self.max_attempts {
+                let task = self.pending.remove(&id).expect("pending task");
+                if let Some(ref mut dead_letter) = self.dead_letter {
+                    dead_letter(id, task.payload);
+                }
+                continue;
+            }
+            self.send(id, attempts + 1, &self.pending[&id].payload)?;
+            let deadline = Instant::now() + self.visibility_timeout;
+            let task = self.pending.get_mut(&id).expect("pending task");
+            task.attempts += 1;
+            task.deadline = deadline;
+            self.deadlines.push(Reverse((deadline, id)));
+        }
+        Ok(())
+    }
+}
+
+/// A task received by a [`ReliableWorker`](struct.ReliableWorker.html).
+#[derive(Debug)]
+pub struct Task {
+    id: u64,
+    attempt: u32,
+    msg: MessageBuffer
+}
+
+impl Task {
+    /// The id of the task.
+    #[inline]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Which attempt this delivery is, starting from 1.
+    #[inline]
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// The payload of the task.
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        &self.msg[HEADER_LEN..]
+    }
+}
+
+/// Receives tasks from a [`ReliableQueue`](struct.ReliableQueue.html), and acknowledges them.
+pub struct ReliableWorker {
+    tasks: Pull,
+    acks: Push
+}
+
+impl ReliableWorker {
+    /// Create a worker.
+    ///
+    /// # Arguments
+    ///
+    /// * `tasks`: The socket tasks are received on.
+    /// * `acks`: The socket acknowledgements are sent on.
+    pub fn new(tasks: Pull, acks: Push) -> ReliableWorker {
+        ReliableWorker { tasks, acks }
+    }
+
+    /// Get a reference to the task socket, for example to connect it.
+    #[inline]
+    pub fn tasks(&self) -> &Pull {
+        &self.tasks
+    }
+
+    /// Get a reference to the acknowledgement socket, for example to connect it.
+    #[inline]
+    pub fn acks(&self) -> &Push {
+        &self.acks
+    }
+
+    /// Receive a task, blocking until one is available.
+    ///
+    /// # Returns
+    ///
+    /// `Err(error::INVALID)` if the message received isn't a task.
+    pub fn recv(&self) -> Result<Task> {
+        parse_task(self.tasks.recv()?)
+    }
+
+    /// Receive a task without blocking.
+    ///
+    /// See [`recv`](#method.recv).
+    pub fn recv_nb(&self) -> Result<Task> {
+        parse_task(self.tasks.recv_nb()?)
+    }
+
+    /// Acknowledge that a task has been completed.
+    ///
+    /// The task won't be sent again once the queue has received the acknowledgement.
+    pub fn ack(&self, task: &Task) -> Result<()> {
+        self.acks.send(MessageBuffer::from_pod(&BigEndian::new(task.id)))?;
+        Ok(())
+    }
+}
+
+fn parse_task(msg: MessageBuffer) -> Result<Task> {
+    if msg.len() < HEADER_LEN {
+        return Err(INVALID);
+    }
+    let id = msg.read_pod::<BigEndian<u64>>(0)?.get();
+    let attempt = msg.read_pod::<BigEndian<u32>>(8)?.get();
+    Ok(Task { id, attempt, msg })
+}
@@ -0,0 +1,161 @@
+//! Content based routing to a set of workers.
+//!
+//! A [`Push`](../protocol/struct.Push.html) socket load balances round robin, so consecutive
+//! messages with the same key may reach different workers. A
+//! [`KeyedPush`](struct.KeyedPush.html) owns one `Push` socket per worker endpoint, and routes
+//! each message to a worker chosen by its key.
+//!
+//! Workers are placed on a consistent hash ring, so adding or removing an endpoint only moves
+//! the keys of roughly one worker's share of the ring.
+use std::collections::BTreeMap;
+
+use alloc::MessageBuffer;
+use error::{Result, INVALID};
+use protocol::{Push, SPSend, SPSocket};
+
+/// The default number of points each endpoint has on the hash ring.
+pub const DEFAULT_REPLICAS: usize = 64;
+
+/// The 64-bit FNV-1a hash of the concatenation of `parts`.
+///
+/// Only explicit bytes are hashed, so that keys are routed the same in every process, whatever
+/// its platform or compiler version.
+fn fnv(parts: &[&[u8]]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &b in parts.iter().flat_map(|part| part.iter()) {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// The position of a replica of an endpoint on the ring.
+fn point(addr: &str, replica: usize) -> u64 {
+    fnv(&[addr.as_bytes(), &(replica as u32).to_be_bytes()])
+}
+
+struct Shard {
+    sock: Push,
+    sent: u64
+}
+
+/// Routes messages to one of several `Push` sockets by key.
+///
+/// `F` extracts the key from a message. Messages with equal keys are always sent to the same
+/// endpoint, for as long as the set of endpoints doesn't change.
+pub struct KeyedPush<F> {
+    key: F,
+    replicas: usize,
+    shards: BTreeMap<String, Shard>,
+    ring: BTreeMap<u64, String>
+}
+
+impl<F, K> KeyedPush<F> where F: Fn(&[u8]) -> K, K: AsRef<[u8]> {
+    /// Create a router with no endpoints.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`: Extracts the routing key from the body of a message, as bytes.
+    pub fn new(key: F) -> KeyedPush<F> {
+        KeyedPush::with_replicas(key, DEFAULT_REPLICAS)
+    }
+
+    /// Create a router, placing each endpoint on the hash ring `replicas` times.
+    ///
+    /// More replicas spread keys more evenly between endpoints, at the cost of a larger ring.
+    pub fn with_replicas(key: F, replicas: usize) -> KeyedPush<F> {
+        KeyedPush {
+            key,
+            replicas: replicas.max(1),
+            shards: BTreeMap::new(),
+            ring: BTreeMap::new()
+        }
+    }
+
+    /// Connect a new `Push` socket to `addr`, and add it to the ring.
+    ///
+    /// Adding an endpoint that is already present has no effect.
+    pub fn add_endpoint(&mut self, addr: &str) -> Result<()> {
+        if self.shards.contains_key(addr) {
+            return Ok(());
+        }
+        let sock = Push::new()?;
+        sock.connect(addr)?;
+        for i in 0..self.replicas {
+            self.ring.insert(point(addr, i), addr.to_string());
+        }
+        self.shards.insert(addr.to_string(), Shard { sock, sent: 0 });
+        Ok(())
+    }
+
+    /// Remove an endpoint, and close its socket.
+    ///
+    /// Only the keys that were routed to `addr` move to other endpoints.
+    ///
+    /// # Returns
+    ///
+    /// `false` if `addr` wasn't an endpoint.
+    pub fn remove_endpoint(&mut self, addr: &str) -> bool {
+        if self.shards.remove(addr).is_none() {
+            return false;
+        }
+        for i in 0..self.replicas {
+            let point = point(addr, i);
+            // Another endpoint may have collided with this point
+            if self.ring.get(&point).is_some_and(|a| a == addr) {
+                self.ring.remove(&point);
+            }
+        }
+        true
+    }
+
+    /// The endpoints, in address order.
+    pub fn endpoints(&self) -> Vec<&str> {
+        self.shards.keys().map(|a| a.as_str()).collect()
+    }
+
+    /// The endpoint that messages with this body are routed to.
+    ///
+    /// Returns `None` if there are no endpoints.
+    pub fn route(&self, body: &[u8]) -> Option<&str> {
+        let point = fnv(&[(self.key)(body).as_ref()]);
+        self.ring.range(point..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, addr)| addr.as_str())
+    }
+
+    /// Send a message to the endpoint for its key.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes sent, or `Err(error::INVALID)` if there are no endpoints.
+    pub fn send(&mut self, buffer: MessageBuffer) -> Result<usize> {
+        self.send_with(buffer, |sock, buffer| sock.send(buffer))
+    }
+
+    /// Send a message to the endpoint for its key, without blocking.
+    ///
+    /// See [`send`](#method.send).
+    pub fn send_nb(&mut self, buffer: MessageBuffer) -> Result<usize> {
+        self.send_with(buffer, |sock, buffer| sock.send_nb(buffer))
+    }
+
+    fn send_with<G>(&mut self, buffer: MessageBuffer, send: G) -> Result<usize>
+        where G: FnOnce(&Push, MessageBuffer) -> Result<usize>
+    {
+        let addr = match self.route(&buffer) {
+            Some(addr) => addr.to_string(),
+            None => return Err(INVALID)
+        };
+        let shard = self.shards.get_mut(&addr).expect("ring entry without a shard");
+        let size = send(&shard.sock, buffer)?;
+        shard.sent += 1;
+        Ok(size)
+    }
+
+    /// The number of messages sent to each endpoint, in address order.
+    pub fn shard_counts(&self) -> Vec<(&str, u64)> {
+        self.shards.iter().map(|(addr, shard)| (addr.as_str(), shard.sent)).collect()
+    }
+}
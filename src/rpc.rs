@@ -0,0 +1,469 @@
+//! Remote procedure calls over request/reply sockets.
+//!
+//! This requires the `serde` feature.
+//!
+//! A [`Server`](struct.Server.html) wraps a [`Rep`](../protocol/struct.Rep.html) socket and
+//! dispatches requests to handlers registered by method name. A
+//! [`Client`](struct.Client.html) wraps a [`Req`](../protocol/struct.Req.html) socket and calls
+//! them. Arguments and results are serialized with a [`Codec`](../codec/trait.Codec.html).
+//!
+//! # Wire Format
+//!
+//! Every message starts with an envelope, followed by the body encoded with the codec. All
+//! integers are big endian.
+//!
+//! A request is `[version: u8][kind = 0: u8][call id: u64][deadline: u64][service version: u64]
+//! [method length: u16][method][arguments]`. The deadline is in milliseconds since the Unix
+//! epoch, or 0 for none, so the clocks of the client and server should be roughly in sync. The
+//! service version identifies the interface the client was built against, or is 0 if it isn't
+//! versioned.
+//!
+//! A successful reply is `[version: u8][kind = 1: u8][call id: u64][result]`, and an error reply
+//! is `[version: u8][kind = 2: u8][call id: u64][code: u8][application code: i32][message]`,
+//! where the message is UTF-8. A request whose envelope can't be decoded gets an error reply with
+//! call id 0, which is never used by a call.
+use std::collections::HashMap;
+use std::fmt;
+use std::result;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use failure::Fail;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use alloc::MessageBuffer;
+use codec::{self, Codec};
+use error::{self, TIMED_OUT, WOULD_BLOCK};
+use poller::poll_readable;
+use protocol::{Rep, Req, SPRecv, SPSend, SPSocket};
+use wire::{self, Reader};
+
+/// The version of the envelope.
+const VERSION: u8 = 1;
+
+const KIND_REQUEST: u8 = 0;
+const KIND_OK: u8 = 1;
+const KIND_ERROR: u8 = 2;
+
+/// Specialized [`Result`](https://doc.rust-lang.org/std/result/enum.Result.html) type for RPC
+/// errors.
+pub type Result<T> = result::Result<T, Error>;
+
+/// The kind of a [`RemoteError`](struct.RemoteError.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// No handler is registered for the method.
+    UnknownMethod,
+    /// The request couldn't be decoded.
+    BadRequest,
+    /// The deadline of the call passed before it was handled.
+    DeadlineExceeded,
+    /// The handler succeeded, but its result couldn't be encoded.
+    Internal,
+    /// The client and server were built against different versions of the service.
+    VersionMismatch,
+    /// An error returned by the handler, with an application defined code.
+    Application(i32)
+}
+
+impl ErrorCode {
+    fn to_wire(self) -> (u8, i32) {
+        match self {
+            ErrorCode::UnknownMethod => (1, 0),
+            ErrorCode::BadRequest => (2, 0),
+            ErrorCode::DeadlineExceeded => (3, 0),
+            ErrorCode::Internal => (4, 0),
+            ErrorCode::Application(code) => (5, code),
+            ErrorCode::VersionMismatch => (6, 0)
+        }
+    }
+
+    fn from_wire(tag: u8, code: i32) -> Option<ErrorCode> {
+        match tag {
+            1 => Some(ErrorCode::UnknownMethod),
+            2 => Some(ErrorCode::BadRequest),
+            3 => Some(ErrorCode::DeadlineExceeded),
+            4 => Some(ErrorCode::Internal),
+            5 => Some(ErrorCode::Application(code)),
+            6 => Some(ErrorCode::VersionMismatch),
+            _ => None
+        }
+    }
+}
+
+/// An error reply from a server.
+///
+/// Handlers return this to report a failure to the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteError {
+    /// The kind of error.
+    pub code: ErrorCode,
+    /// A human readable description.
+    pub message: String
+}
+
+impl RemoteError {
+    /// Create an error.
+    pub fn new<M: Into<String>>(code: ErrorCode, message: M) -> RemoteError {
+        RemoteError { code, message: message.into() }
+    }
+
+    /// Create an application error.
+    pub fn application<M: Into<String>>(code: i32, message: M) -> RemoteError {
+        RemoteError::new(ErrorCode::Application(code), message)
+    }
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl Fail for RemoteError {}
+
+/// An error from making or serving a call.
+#[derive(Debug)]
+pub enum Error {
+    /// Sending, receiving, or serializing failed.
+    Codec(codec::Error),
+    /// The server replied with an error.
+    Remote(RemoteError),
+    /// A message didn't have a valid envelope.
+    Malformed
+}
+
+impl From<error::Error> for Error {
+    fn from(err: error::Error) -> Error {
+        Error::Codec(codec::Error::Nanomsg(err))
+    }
+}
+
+impl From<codec::Error> for Error {
+    fn from(err: codec::Error) -> Error {
+        Error::Codec(err)
+    }
+}
+
+impl From<wire::Malformed> for Error {
+    fn from(_: wire::Malformed) -> Error {
+        Error::Malformed
+    }
+}
+
+impl From<RemoteError> for Error {
+    fn from(err: RemoteError) -> Error {
+        Error::Remote(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Codec(ref e) => e.fmt(f),
+            Error::Remote(ref e) => write!(f, "Remote error: {}", e),
+            Error::Malformed => f.write_str("Malformed RPC envelope")
+        }
+    }
+}
+
+impl Fail for Error {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            Error::Codec(ref e) => Some(e),
+            Error::Remote(ref e) => Some(e),
+            Error::Malformed => None
+        }
+    }
+}
+
+/// Build a message from an envelope and a body.
+fn message(envelope: &[u8], body: &[u8]) -> MessageBuffer {
+    let mut msg = MessageBuffer::new(envelope.len() + body.len());
+    msg[..envelope.len()].copy_from_slice(envelope);
+    msg[envelope.len()..].copy_from_slice(body);
+    msg
+}
+
+fn reply_envelope(kind: u8, id: u64) -> Vec<u8> {
+    let mut envelope = Vec::with_capacity(10);
+    envelope.push(VERSION);
+    envelope.push(kind);
+    envelope.extend_from_slice(&id.to_be_bytes());
+    envelope
+}
+
+/// Build a reply message.
+///
+/// This is the low level counterpart of [`Call::decode`](struct.Call.html#method.decode), for
+/// servers that don't use [`Server`](struct.Server.html).
+///
+/// # Arguments
+///
+/// * `id`: The id of the call being replied to.
+/// * `result`: The encoded result of the call, or the error to report.
+pub fn encode_reply(id: u64, result: result::Result<&[u8], &RemoteError>) -> MessageBuffer {
+    match result {
+        Ok(body) => message(&reply_envelope(KIND_OK, id), body),
+        Err(err) => {
+            let mut envelope = reply_envelope(KIND_ERROR, id);
+            let (tag, code) = err.code.to_wire();
+            envelope.push(tag);
+            envelope.extend_from_slice(&code.to_be_bytes());
+            message(&envelope, err.message.as_bytes())
+        }
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// A call decoded from a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call<'a> {
+    /// The id of the call, which the reply must carry.
+    pub id: u64,
+    /// The deadline, in milliseconds since the Unix epoch, or 0 for none.
+    pub deadline: u64,
+    /// The service version of the client, or 0 if it isn't versioned.
+    pub service_version: u64,
+    /// The name of the method.
+    pub method: &'a str,
+    /// The encoded arguments.
+    pub args: &'a [u8]
+}
+
+impl<'a> Call<'a> {
+    /// Decode the envelope of a request.
+    ///
+    /// # Returns
+    ///
+    /// `Err(Error::Malformed)` if the request doesn't have a valid envelope.
+    pub fn decode(request: &'a [u8]) -> Result<Call<'a>> {
+        let mut reader = Reader::new(request);
+        if reader.u8()? != VERSION || reader.u8()? != KIND_REQUEST {
+            return Err(Error::Malformed);
+        }
+        let id = reader.u64()?;
+        let deadline = reader.u64()?;
+        let service_version = reader.u64()?;
+        let method = reader.str()?;
+        Ok(Call { id, deadline, service_version, method, args: reader.rest() })
+    }
+
+    /// Check that the call can be handled by a server for `service_version`.
+    ///
+    /// A version of 0 on either side isn't checked.
+    ///
+    /// # Returns
+    ///
+    /// An error with `ErrorCode::DeadlineExceeded` if the deadline has passed, or
+    /// `ErrorCode::VersionMismatch` if the versions differ.
+    pub fn check(&self, service_version: u64) -> result::Result<(), RemoteError> {
+        if self.deadline != 0 && unix_millis(SystemTime::now()) > self.deadline {
+            return Err(RemoteError::new(ErrorCode::DeadlineExceeded, "deadline exceeded"));
+        }
+        if self.service_version != 0 && service_version != 0 && self.service_version != service_version {
+            return Err(RemoteError::new(
+                ErrorCode::VersionMismatch,
+                format!("client version {:016x}, server version {:016x}", self.service_version, service_version)));
+        }
+        Ok(())
+    }
+}
+
+type Handler<C> = Box<dyn FnMut(&C, &[u8]) -> result::Result<MessageBuffer, RemoteError>>;
+
+/// Serves calls received on a `Rep` socket.
+pub struct Server<C> {
+    sock: Rep,
+    codec: C,
+    service_version: u64,
+    handlers: HashMap<String, Handler<C>>
+}
+
+impl<C: Codec> Server<C> {
+    /// Create a server with no methods.
+    pub fn new(sock: Rep, codec: C) -> Server<C> {
+        Server { sock, codec, service_version: 0, handlers: HashMap::new() }
+    }
+
+    /// Set the service version, so that calls from clients with a different version are
+    /// rejected with `ErrorCode::VersionMismatch`.
+    pub fn set_service_version(&mut self, version: u64) {
+        self.service_version = version;
+    }
+
+    /// Get a reference to the socket, for example to bind it.
+    #[inline]
+    pub fn get_ref(&self) -> &Rep {
+        &self.sock
+    }
+
+    /// Register the handler for `method`, replacing any previous handler.
+    ///
+    /// Arguments that can't be decoded as `A` are rejected with `ErrorCode::BadRequest` before
+    /// the handler is called.
+    pub fn register<A, R, F>(&mut self, method: &str, mut handler: F)
+        where A: DeserializeOwned,
+              R: Serialize,
+              F: FnMut(A) -> result::Result<R, RemoteError> + 'static
+    {
+        self.handlers.insert(method.to_string(), Box::new(move |codec: &C, body: &[u8]| {
+            let args = codec.decode(body)
+                .map_err(|e| RemoteError::new(ErrorCode::BadRequest, e.to_string()))?;
+            let result = handler(args)?;
+            codec.encode(&result).map_err(|e| RemoteError::new(ErrorCode::Internal, e.to_string()))
+        }));
+    }
+
+    /// Receive a single call, and send its reply.
+    ///
+    /// Blocks until a call is received.
+    pub fn handle(&mut self) -> Result<()> {
+        let request = self.sock.recv()?;
+        let reply = self.dispatch(&request);
+        self.sock.send(reply)?;
+        Ok(())
+    }
+
+    /// Serve calls until an error occurs.
+    ///
+    /// # Returns
+    ///
+    /// The error that stopped the server, for example `error::TERMINATING` once
+    /// [`Socket::terminate`](../socket/struct.Socket.html#method.terminate) is called.
+    pub fn serve(&mut self) -> Error {
+        loop {
+            if let Err(e) = self.handle() {
+                return e;
+            }
+        }
+    }
+
+    fn dispatch(&mut self, request: &[u8]) -> MessageBuffer {
+        let call = match Call::decode(request) {
+            Ok(call) => call,
+            Err(_) => return encode_reply(0, Err(&RemoteError::new(ErrorCode::BadRequest, "malformed envelope")))
+        };
+        let service_version = self.service_version;
+        let result = call.check(service_version).and_then(|()| match self.handlers.get_mut(call.method) {
+            Some(handler) => handler(&self.codec, call.args),
+            None => Err(RemoteError::new(ErrorCode::UnknownMethod, format!("unknown method {}", call.method)))
+        });
+        match result {
+            Ok(body) => encode_reply(call.id, Ok(&body)),
+            Err(err) => encode_reply(call.id, Err(&err))
+        }
+    }
+}
+
+/// Makes calls on a `Req` socket.
+pub struct Client<C> {
+    sock: Req,
+    codec: C,
+    next_id: u64,
+    service_version: u64,
+    timeout: Option<Duration>
+}
+
+impl<C: Codec> Client<C> {
+    /// Create a client, without a timeout.
+    pub fn new(sock: Req, codec: C) -> Client<C> {
+        Client { sock, codec, next_id: 1, service_version: 0, timeout: None }
+    }
+
+    /// Set the service version sent with every call.
+    ///
+    /// See [`Server::set_service_version`](struct.Server.html#method.set_service_version).
+    pub fn set_service_version(&mut self, version: u64) {
+        self.service_version = version;
+    }
+
+    /// Get a reference to the socket, for example to connect it.
+    #[inline]
+    pub fn get_ref(&self) -> &Req {
+        &self.sock
+    }
+
+    /// Set how long calls wait for a reply.
+    ///
+    /// The deadline is sent to the server, which rejects calls that are received after it has
+    /// passed. `None` waits forever.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Call `method` with `args`, and wait for the result.
+    ///
+    /// # Returns
+    ///
+    /// The decoded result, `Err(Error::Remote(..))` if the server replied with an error, or
+    /// `Err(Error::Codec(codec::Error::Nanomsg(error::TIMED_OUT)))` if the timeout expired.
+    pub fn call<A, R>(&mut self, method: &str, args: &A) -> Result<R>
+        where A: Serialize + ?Sized,
+              R: DeserializeOwned
+    {
+        if method.len() > u16::MAX as usize {
+            return Err(Error::Codec(codec::Error::Nanomsg(error::INVALID)));
+        }
+        let id = self.next_id;
+        // Call id 0 is reserved for replies to malformed requests
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        let deadline = self.timeout.map(|t| (Instant::now() + t, SystemTime::now() + t));
+
+        let mut envelope = Vec::with_capacity(28 + method.len());
+        envelope.push(VERSION);
+        envelope.push(KIND_REQUEST);
+        envelope.extend_from_slice(&id.to_be_bytes());
+        envelope.extend_from_slice(&deadline.map_or(0, |(_, wall)| unix_millis(wall)).to_be_bytes());
+        envelope.extend_from_slice(&self.service_version.to_be_bytes());
+        envelope.extend_from_slice(&(method.len() as u16).to_be_bytes());
+        envelope.extend_from_slice(method.as_bytes());
+        let body = self.codec.encode(args)?;
+        self.sock.send(message(&envelope, &body))?;
+
+        loop {
+            let reply = self.recv_reply(deadline.map(|(at, _)| at))?;
+            let mut reader = Reader::new(&reply);
+            if reader.u8()? != VERSION {
+                return Err(Error::Malformed);
+            }
+            let kind = reader.u8()?;
+            let reply_id = reader.u64()?;
+            // Skip replies to earlier calls that timed out. An error with id 0 means the server
+            // couldn't decode the request, and the socket only has one request outstanding.
+            if reply_id != id && !(reply_id == 0 && kind == KIND_ERROR) {
+                continue;
+            }
+            return match kind {
+                KIND_OK => Ok(self.codec.decode(reader.rest())?),
+                KIND_ERROR => {
+                    let tag = reader.u8()?;
+                    let code = ErrorCode::from_wire(tag, reader.i32()?).ok_or(Error::Malformed)?;
+                    let message = String::from_utf8_lossy(reader.rest()).into_owned();
+                    Err(Error::Remote(RemoteError { code, message }))
+                },
+                _ => Err(Error::Malformed)
+            };
+        }
+    }
+
+    fn recv_reply(&self, deadline: Option<Instant>) -> error::Result<MessageBuffer> {
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => return self.sock.recv()
+        };
+        loop {
+            if !poll_readable(self.sock.socket(), Some(deadline))? {
+                return Err(TIMED_OUT);
+            }
+            match self.sock.recv_nb() {
+                Err(WOULD_BLOCK) => continue,
+                res => return res
+            }
+        }
+    }
+}
+
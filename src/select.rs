@@ -0,0 +1,121 @@
+//! Receiving from whichever of several sockets is ready first.
+//!
+//! [`select`](fn.select.html) and the [`select_recv!`](../macro.select_recv.html) macro wait
+//! until one of a set of sockets has a message, and return that message along with the index of
+//! the socket it was received on. The sockets may use different protocols.
+//!
+//! When several sockets are ready at once, they are served in turn rather than always favouring
+//! the first one. A [`Selector`](struct.Selector.html) keeps track of whose turn it is; the free
+//! function uses a selector per thread.
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+use alloc::MessageBuffer;
+use error::{Result, INVALID, TIMED_OUT, WOULD_BLOCK};
+use poller::poll_until;
+use protocol::SPRecv;
+use socket::{Flags, Poll, Socket};
+
+/// A socket that can be passed to [`select`](fn.select.html).
+///
+/// [`SPRecv`](../protocol/trait.SPRecv.html) can't be used as a trait object, since it has an
+/// associated type. This is implemented for every socket that implements `SPRecv`.
+pub trait Selectable {
+    /// Get the underlying socket.
+    fn select_socket(&self) -> &Socket;
+}
+
+impl<S: SPRecv> Selectable for S {
+    #[inline]
+    fn select_socket(&self) -> &Socket {
+        self.socket()
+    }
+}
+
+/// Fair selection over a set of sockets.
+///
+/// The selector remembers which socket was served last, and starts looking after it on the
+/// next call.
+#[derive(Debug, Default)]
+pub struct Selector {
+    next: usize
+}
+
+impl Selector {
+    /// Create a new selector.
+    pub fn new() -> Selector {
+        Selector { next: 0 }
+    }
+
+    /// Receive a message from the first of `socks` that is ready.
+    ///
+    /// # Arguments
+    ///
+    /// * `socks`: The sockets to receive from.
+    /// * `timeout`: How long to wait for a message. `None` waits forever.
+    ///
+    /// # Returns
+    ///
+    /// The index in `socks` of the socket the message was received on, and the message.
+    ///
+    /// `Err(error::TIMED_OUT)` if no message was received before the timeout, and
+    /// `Err(error::INVALID)` if `socks` is empty.
+    pub fn select(&mut self, socks: &[&dyn Selectable], timeout: Option<Duration>) -> Result<(usize, MessageBuffer)> {
+        if socks.is_empty() {
+            return Err(INVALID);
+        }
+        let mut polls = socks.iter()
+            .map(|s| s.select_socket().make_poll(true, false))
+            .collect::<Vec<Poll>>();
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            if poll_until(&mut polls, deadline)? == 0 {
+                return Err(TIMED_OUT);
+            }
+            let start = self.next % socks.len();
+            for i in (start..socks.len()).chain(0..start) {
+                if !polls[i].can_receive() {
+                    continue;
+                }
+                match socks[i].select_socket().recv(Flags::DONTWAIT) {
+                    Ok(msg) => {
+                        self.next = i + 1;
+                        return Ok((i, msg));
+                    },
+                    // Another thread received the message first
+                    Err(WOULD_BLOCK) => {},
+                    Err(e) => return Err(e)
+                }
+            }
+        }
+    }
+}
+
+thread_local!{
+    static SELECTOR: RefCell<Selector> = RefCell::new(Selector::new());
+}
+
+/// Receive a message from the first of `socks` that is ready.
+///
+/// This uses a [`Selector`](struct.Selector.html) per thread, so repeated calls from the same
+/// thread are fair across the sockets.
+///
+/// # See Also
+///
+/// * [`Selector::select`](struct.Selector.html#method.select)
+/// * [`select_recv!`](../macro.select_recv.html)
+pub fn select(socks: &[&dyn Selectable], timeout: Option<Duration>) -> Result<(usize, MessageBuffer)> {
+    SELECTOR.with(|selector| selector.borrow_mut().select(socks, timeout))
+}
+
+/// Receive a message from whichever of the given sockets is ready first.
+///
+/// The sockets are listed after the timeout, which is an `Option<Duration>`. This expands to a
+/// call to [`select::select`](select/fn.select.html), and evaluates to the index of the socket
+/// and the message. For example `select_recv!(Some(timeout); sub, pull, pair)`.
+#[macro_export]
+macro_rules! select_recv {
+    ($timeout:expr; $($sock:expr),+ $(,)*) => {
+        $crate::select::select(&[$(&$sock as &dyn $crate::select::Selectable),+], $timeout)
+    }
+}
@@ -0,0 +1,238 @@
+//! Sequence numbers and gap detection for publish/subscribe.
+//!
+//! A [`Pub`](../protocol/struct.Pub.html) socket drops messages for subscribers that can't keep
+//! up, without telling either side. A [`SequencedPub`](struct.SequencedPub.html) numbers the
+//! messages of each topic, and a [`SequencedSub`](struct.SequencedSub.html) uses the numbers to
+//! report the messages it missed, and the ones it received twice.
+//!
+//! Each topic should have a single publisher, otherwise the sequences of the publishers are
+//! interleaved and look like gaps and duplicates.
+//!
+//! # Wire Format
+//!
+//! A message is `[topic][body][sequence: u64][epoch: u64][topic length: u16]`, with the integers
+//! in big endian. The trailer is at the end so that `Sub` sockets can still subscribe by topic
+//! prefix. Sequences start at 1 for each topic. The epoch is chosen at random by each publisher,
+//! so that a subscriber can tell a restarted publisher from duplicates.
+use std::collections::{HashMap, VecDeque};
+
+use alloc::MessageBuffer;
+use error::{Result, INVALID};
+use pod::BigEndian;
+use protocol::{Pub, SPRecv, SPSend, Sub};
+use random::random_id;
+
+/// The size of the trailer.
+const TRAILER_LEN: usize = 18;
+
+/// Publishes messages with a sequence number per topic.
+pub struct SequencedPub {
+    sock: Pub,
+    epoch: u64,
+    sequences: HashMap<Vec<u8>, u64>
+}
+
+impl SequencedPub {
+    /// Create a publisher with a random epoch, and every topic starting at sequence 1.
+    pub fn new(sock: Pub) -> SequencedPub {
+        SequencedPub { sock, epoch: random_id(), sequences: HashMap::new() }
+    }
+
+    /// The epoch of the publisher, sent with every message.
+    #[inline]
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Get a reference to the socket, for example to bind it.
+    #[inline]
+    pub fn get_ref(&self) -> &Pub {
+        &self.sock
+    }
+
+    /// Publish a message on a topic.
+    ///
+    /// # Returns
+    ///
+    /// The sequence number of the message.
+    /// `Err(error::INVALID)` if the topic is longer than `u16::MAX` bytes.
+    pub fn send(&mut self, topic: &[u8], body: &[u8]) -> Result<u64> {
+        if topic.len() > u16::MAX as usize {
+            return Err(INVALID);
+        }
+        let seq = self.sequences.get(topic).map_or(1, |s| s + 1);
+        let len = topic.len() + body.len();
+        let mut msg = MessageBuffer::new(len + TRAILER_LEN);
+        msg[..topic.len()].copy_from_slice(topic);
+        msg[topic.len()..len].copy_from_slice(body);
+        msg.write_pod(len, &BigEndian::new(seq))?;
+        msg.write_pod(len + 8, &BigEndian::new(self.epoch))?;
+        msg.write_pod(len + 16, &BigEndian::new(topic.len() as u16))?;
+        self.sock.send(msg)?;
+        self.sequences.insert(topic.to_vec(), seq);
+        Ok(seq)
+    }
+
+    /// The sequence number of the last message published on a topic, or 0 if there was none.
+    pub fn sequence(&self, topic: &[u8]) -> u64 {
+        self.sequences.get(topic).cloned().unwrap_or(0)
+    }
+}
+
+/// A message received by a [`SequencedSub`](struct.SequencedSub.html).
+#[derive(Debug)]
+pub struct SequencedMessage {
+    msg: MessageBuffer,
+    topic_len: usize,
+    sequence: u64
+}
+
+impl SequencedMessage {
+    /// The topic of the message.
+    #[inline]
+    pub fn topic(&self) -> &[u8] {
+        &self.msg[..self.topic_len]
+    }
+
+    /// The body of the message, after the topic.
+    #[inline]
+    pub fn body(&self) -> &[u8] {
+        &self.msg[self.topic_len..]
+    }
+
+    /// The sequence number of the message.
+    #[inline]
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// The topic followed by the body, without the sequence number.
+    #[inline]
+    pub fn into_buffer(self) -> MessageBuffer {
+        self.msg
+    }
+}
+
+/// What a [`SequencedSub`](struct.SequencedSub.html) received.
+#[derive(Debug)]
+pub enum RecvEvent {
+    /// A message in sequence.
+    Message(SequencedMessage),
+    /// Messages were missed on a topic.
+    ///
+    /// This is reported just before the message that followed the gap.
+    Gap {
+        /// The topic the messages were missed on.
+        topic: Vec<u8>,
+        /// The number of messages missed.
+        missed: u64
+    },
+    /// A message was received again, or out of order, and has been dropped.
+    Duplicate {
+        /// The topic of the message.
+        topic: Vec<u8>,
+        /// The sequence number of the message.
+        sequence: u64
+    },
+    /// The publisher of a topic restarted with a new epoch, so its sequence started again.
+    ///
+    /// This is reported just before the first message received from the new publisher.
+    Reset {
+        /// The topic that was reset.
+        topic: Vec<u8>
+    }
+}
+
+/// Receives messages from a [`SequencedPub`](struct.SequencedPub.html), and detects gaps and
+/// duplicates.
+///
+/// The first message received on a topic is accepted whatever its sequence number, since the
+/// subscriber may have joined after the publisher started.
+pub struct SequencedSub {
+    sock: Sub,
+    /// The epoch and next sequence number expected on each topic.
+    expected: HashMap<Vec<u8>, (u64, u64)>,
+    events: VecDeque<RecvEvent>
+}
+
+impl SequencedSub {
+    /// Create a subscriber that hasn't seen any topics.
+    ///
+    /// The socket still has to subscribe to the topics it wants to receive.
+    pub fn new(sock: Sub) -> SequencedSub {
+        SequencedSub { sock, expected: HashMap::new(), events: VecDeque::new() }
+    }
+
+    /// Get a reference to the socket, for example to connect or subscribe it.
+    #[inline]
+    pub fn get_ref(&self) -> &Sub {
+        &self.sock
+    }
+
+    /// Receive the next event, blocking until a message is available.
+    ///
+    /// # Returns
+    ///
+    /// `Err(error::INVALID)` if the message received has no valid trailer.
+    pub fn recv(&mut self) -> Result<RecvEvent> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        let msg = self.sock.recv()?;
+        self.handle(msg)
+    }
+
+    /// Receive the next event without blocking.
+    ///
+    /// See [`recv`](#method.recv).
+    pub fn recv_nb(&mut self) -> Result<RecvEvent> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        let msg = self.sock.recv_nb()?;
+        self.handle(msg)
+    }
+
+    /// Forget the sequence of a topic, so that the next message on it is accepted as is.
+    ///
+    /// This should be called after unsubscribing from a topic.
+    pub fn forget(&mut self, topic: &[u8]) {
+        self.expected.remove(topic);
+    }
+
+    fn handle(&mut self, mut msg: MessageBuffer) -> Result<RecvEvent> {
+        if msg.len() < TRAILER_LEN {
+            return Err(INVALID);
+        }
+        let len = msg.len() - TRAILER_LEN;
+        let sequence = msg.read_pod::<BigEndian<u64>>(len)?.get();
+        let epoch = msg.read_pod::<BigEndian<u64>>(len + 8)?.get();
+        let topic_len = msg.read_pod::<BigEndian<u16>>(len + 16)?.get() as usize;
+        if topic_len > len || sequence == 0 {
+            return Err(INVALID);
+        }
+        msg.resize(len);
+        let topic = msg[..topic_len].to_vec();
+
+        let expected = self.expected.get(&topic).cloned();
+        let event = match expected {
+            Some((last_epoch, _)) if last_epoch != epoch => Some(RecvEvent::Reset { topic: topic.clone() }),
+            Some((_, expected)) if sequence < expected => {
+                return Ok(RecvEvent::Duplicate { topic, sequence });
+            },
+            Some((_, expected)) if sequence > expected => {
+                Some(RecvEvent::Gap { topic: topic.clone(), missed: sequence - expected })
+            },
+            _ => None
+        };
+        self.expected.insert(topic, (epoch, sequence + 1));
+        let message = RecvEvent::Message(SequencedMessage { msg, topic_len, sequence });
+        match event {
+            Some(event) => {
+                self.events.push_back(message);
+                Ok(event)
+            },
+            None => Ok(message)
+        }
+    }
+}
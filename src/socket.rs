@@ -5,6 +5,7 @@
 use std::ffi::CString;
 use std::mem;
 use std::ptr;
+use std::result;
 #[cfg(windows)]
 use std::os::windows::io::RawSocket;
 
@@ -120,6 +121,44 @@ pub enum Protocol {
     Pair = NN_PAIR
 }
 
+/// The scatter array of a message, see [nn_sendmsg(3)](http://nanomsg.org/v1.1.2/nn_sendmsg.html).
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct nn_iovec {
+    iov_base: *mut c_void,
+    iov_len: size_t
+}
+
+/// The message header used by `nn_sendmsg` and `nn_recvmsg`.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct nn_msghdr {
+    msg_iov: *mut nn_iovec,
+    msg_iovlen: c_int,
+    msg_control: *mut c_void,
+    msg_controllen: size_t
+}
+
+/// The SP headers of a message received on a raw socket.
+///
+/// The headers are opaque. They carry the routing information (for example the backtrace of a
+/// request) that raw sockets need to forward a message to the right peer, so a message received
+/// with [`Socket::recv_msg`](struct.Socket.html#method.recv_msg) should be sent on with its
+/// header.
+#[derive(Debug)]
+pub struct MessageHeader(*mut c_void);
+
+impl Drop for MessageHeader {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { nn_freemsg(self.0) };
+        }
+    }
+}
+
+// The header uniquely owns its nanomsg chunk, like a MessageBuffer.
+unsafe impl Send for MessageHeader {}
+
 /// A request for polling a socket and the poll result
 #[derive(Copy, Clone)]
 pub struct Poll(nn_pollfd);
@@ -273,11 +312,92 @@ impl Socket {
     /// * [nn_send(3)](http://nanomsg.org/v1.1.2/nn_send.html)
     /// * [`send_buf`](#method.send_buf)
     pub fn send(&self, buffer: MessageBuffer, flags: Flags) -> Result<usize> {
+        self.try_send(buffer, flags).map_err(|(err, _)| err)
+    }
+
+    /// Send a message, returning the buffer if it couldn't be sent.
+    ///
+    /// nanomsg only takes ownership of the message if it is sent successfully. This is
+    /// like [`send`](#method.send), but on failure the unsent buffer is returned along with
+    /// the error, so that sending can be retried (for example after `Err(error::WOULD_BLOCK)`
+    /// in non-blocking mode).
+    ///
+    /// # See Also
+    /// * [nn_send(3)](http://nanomsg.org/v1.1.2/nn_send.html)
+    /// * [`send`](#method.send)
+    pub fn try_send(&self, buffer: MessageBuffer, flags: Flags) -> result::Result<usize, (Error, MessageBuffer)> {
+        let len = buffer.len();
+        let buf_ptr = unsafe { buffer.into_raw() };
         let size = unsafe {
-            let buf_ptr = buffer.into_raw();
             nn_send(self.0, &buf_ptr as *const _ as *const c_void, NN_MSG, flags.bits)
         };
+        if size == -1 {
+            let err = last_error();
+            return Err((err, unsafe { MessageBuffer::from_raw(buf_ptr, len) }));
+        }
+        Ok(size as usize)
+    }
+
+    /// Receive a message along with its SP headers.
+    ///
+    /// This is useful with raw sockets, where the headers have to be preserved for a message to
+    /// be routed back to its origin.
+    ///
+    /// # See Also
+    /// * [nn_recvmsg(3)](http://nanomsg.org/v1.1.2/nn_recvmsg.html)
+    /// * [`send_msg`](#method.send_msg)
+    pub fn recv_msg(&self, flags: Flags) -> Result<(MessageBuffer, MessageHeader)> {
+        let mut body: *mut c_void = ptr::null_mut();
+        let mut control: *mut c_void = ptr::null_mut();
+        let mut iov = nn_iovec {
+            iov_base: &mut body as *mut _ as *mut c_void,
+            iov_len: NN_MSG
+        };
+        let mut hdr = nn_msghdr {
+            msg_iov: &mut iov,
+            msg_iovlen: 1,
+            msg_control: &mut control as *mut _ as *mut c_void,
+            msg_controllen: NN_MSG
+        };
+        let size = unsafe { nn_recvmsg(self.0, &mut hdr as *mut _ as *mut c_void, flags.bits) };
         error_guard!(size);
+        Ok(unsafe {
+            (MessageBuffer::from_raw(body, size as usize), MessageHeader(control))
+        })
+    }
+
+    /// Send a message with the SP headers it was received with.
+    ///
+    /// # Note
+    ///
+    /// nanomsg frees the header even if sending fails, so unlike
+    /// [`try_send`](#method.try_send) the message can't be retried, and is dropped on failure.
+    ///
+    /// # See Also
+    /// * [nn_sendmsg(3)](http://nanomsg.org/v1.1.2/nn_sendmsg.html)
+    /// * [`recv_msg`](#method.recv_msg)
+    pub fn send_msg(&self, buffer: MessageBuffer, header: MessageHeader, flags: Flags) -> Result<usize> {
+        let len = buffer.len();
+        let mut buf_ptr = unsafe { buffer.into_raw() };
+        let mut control = header.0;
+        mem::forget(header);
+        let mut iov = nn_iovec {
+            iov_base: &mut buf_ptr as *mut _ as *mut c_void,
+            iov_len: NN_MSG
+        };
+        let hdr = nn_msghdr {
+            msg_iov: &mut iov,
+            msg_iovlen: 1,
+            msg_control: if control.is_null() { ptr::null_mut() } else { &mut control as *mut _ as *mut c_void },
+            msg_controllen: if control.is_null() { 0 } else { NN_MSG }
+        };
+        let size = unsafe { nn_sendmsg(self.0, &hdr as *const _ as *const c_void, flags.bits) };
+        if size == -1 {
+            let err = last_error();
+            // The body is left to the caller, and the header has already been freed.
+            drop(unsafe { MessageBuffer::from_raw(buf_ptr, len) });
+            return Err(err);
+        }
         Ok(size as usize)
     }
 
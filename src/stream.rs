@@ -0,0 +1,172 @@
+//! Runtime independent `Stream` and `Sink` adapters.
+//!
+//! This requires the `futures` feature.
+//!
+//! [`RecvStream`](struct.RecvStream.html) implements `futures::Stream` for any socket that can
+//! receive messages, and [`SendSink`](struct.SendSink.html) implements `futures::Sink` for any
+//! socket that can send messages. The poll descriptors of the sockets are driven by
+//! [async-io](https://docs.rs/async-io), which runs its own reactor, so the adapters can be used
+//! with any executor (for example smol, async-std, or a tokio runtime).
+use std::os::unix::io::{AsFd, BorrowedFd, RawFd};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use async_io::Async;
+use futures_core::Stream;
+use futures_sink::Sink;
+
+use alloc::MessageBuffer;
+use error::{Error, Result, TERMINATING, WOULD_BLOCK};
+use protocol::{SPRecv, SPSend};
+use socket::Flags;
+
+/// A nanomsg poll descriptor.
+///
+/// The descriptor is owned by the nanomsg socket, so this doesn't close it, and it must not
+/// outlive the socket.
+#[derive(Debug)]
+struct PollFd(RawFd);
+
+impl AsFd for PollFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        unsafe { BorrowedFd::borrow_raw(self.0) }
+    }
+}
+
+fn poll_fd(fd: RawFd) -> Result<Async<PollFd>> {
+    // The nanomsg descriptors are already non-blocking, and their flags shouldn't be changed.
+    Ok(Async::new_nonblocking(PollFd(fd))?)
+}
+
+/// A stream of the messages received on a socket.
+///
+/// Each item is the result of receiving a message. The stream ends once nanomsg is terminated
+/// (see [`Socket::terminate`](../socket/struct.Socket.html#method.terminate)).
+pub struct RecvStream<S> {
+    // Declared first, so that the descriptor is deregistered before the socket closes.
+    fd: Async<PollFd>,
+    sock: S,
+    done: bool
+}
+
+impl<S: SPRecv> RecvStream<S> {
+    /// Create a stream of the messages received on `sock`.
+    pub fn new(sock: S) -> Result<RecvStream<S>> {
+        let fd = poll_fd(sock.recv_poll_fd()?)?;
+        Ok(RecvStream { fd, sock, done: false })
+    }
+
+    /// Get a reference to the underlying socket.
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.sock
+    }
+
+    /// Deregister the socket from the reactor, and return it.
+    pub fn into_inner(self) -> S {
+        self.sock
+    }
+}
+
+// The socket is never pinned, so the stream can be moved freely.
+impl<S> Unpin for RecvStream<S> {}
+
+impl<S: SPRecv> Stream for RecvStream<S> {
+    type Item = Result<MessageBuffer>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<MessageBuffer>>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        loop {
+            match this.sock.recv_nb() {
+                Ok(msg) => return Poll::Ready(Some(Ok(msg))),
+                Err(WOULD_BLOCK) => {
+                    if let Err(e) = ready!(this.fd.poll_readable(cx)) {
+                        return Poll::Ready(Some(Err(Error::from(e))));
+                    }
+                },
+                Err(TERMINATING) => {
+                    this.done = true;
+                    return Poll::Ready(None);
+                },
+                Err(e) => return Poll::Ready(Some(Err(e)))
+            }
+        }
+    }
+}
+
+/// A sink that sends messages on a socket.
+///
+/// The sink buffers at most one message. [`poll_ready`](#method.poll_ready) only succeeds once
+/// the previous message has been handed to nanomsg, so backpressure from the socket (for
+/// example a full send buffer, or no connected peers for a `Push` socket) propagates to the
+/// producer.
+pub struct SendSink<S> {
+    // Declared first, so that the descriptor is deregistered before the socket closes.
+    fd: Async<PollFd>,
+    sock: S,
+    pending: Option<MessageBuffer>
+}
+
+impl<S: SPSend> SendSink<S> {
+    /// Create a sink that sends messages on `sock`.
+    pub fn new(sock: S) -> Result<SendSink<S>> {
+        let fd = poll_fd(sock.send_poll_fd()?)?;
+        Ok(SendSink { fd, sock, pending: None })
+    }
+
+    /// Get a reference to the underlying socket.
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.sock
+    }
+
+    /// Deregister the socket from the reactor, and return it.
+    ///
+    /// A message that hasn't been flushed yet is dropped.
+    pub fn into_inner(self) -> S {
+        self.sock
+    }
+
+    fn poll_send_pending(&mut self, cx: &mut Context) -> Poll<Result<()>> {
+        while let Some(msg) = self.pending.take() {
+            match self.sock.socket().try_send(msg, Flags::DONTWAIT) {
+                Ok(_) => {},
+                Err((WOULD_BLOCK, msg)) => {
+                    self.pending = Some(msg);
+                    ready!(self.fd.poll_readable(cx)).map_err(Error::from)?;
+                },
+                Err((e, _)) => return Poll::Ready(Err(e))
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+// The socket is never pinned, so the sink can be moved freely.
+impl<S> Unpin for SendSink<S> {}
+
+impl<S: SPSend> Sink<MessageBuffer> for SendSink<S> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: MessageBuffer) -> Result<()> {
+        let this = self.get_mut();
+        assert!(this.pending.is_none(), "start_send called without poll_ready");
+        this.pending = Some(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+}
@@ -0,0 +1,344 @@
+//! Asynchronous sockets for the [tokio](https://docs.rs/tokio) runtime.
+//!
+//! This requires the `tokio` feature.
+//!
+//! [`AsyncSocket`](struct.AsyncSocket.html) wraps one of the [protocol](../protocol/index.html)
+//! sockets, and registers its [`recv_poll_fd`](../protocol/trait.SPRecv.html#method.recv_poll_fd)
+//! and [`send_poll_fd`](../protocol/trait.SPSend.html#method.send_poll_fd) descriptors with the
+//! tokio reactor. Messages are then received and sent in non-blocking mode, waiting for the
+//! descriptors to become ready whenever nanomsg reports `error::WOULD_BLOCK`.
+//!
+//! The [`AsyncRecv`](trait.AsyncRecv.html) and [`AsyncSend`](trait.AsyncSend.html) traits are
+//! the asynchronous counterparts of [`SPRecv`](../protocol/trait.SPRecv.html) and
+//! [`SPSend`](../protocol/trait.SPSend.html), and like them are only implemented for the sockets
+//! that can receive or send.
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use nanomsg_sys::{NN_SOL_SOCKET, NN_RCVFD, NN_SNDFD};
+use tokio::io::Interest;
+use tokio::io::unix::AsyncFd;
+use tokio::time::{sleep, Sleep};
+
+use alloc::MessageBuffer;
+use error::{Result, TIMED_OUT, WOULD_BLOCK};
+use protocol::{
+    Pub, Sub,
+    Bus,
+    Req, Rep,
+    Push, Pull,
+    Surveyor, Respondent,
+    Pair,
+    SPSocket, SPRecv, SPSend
+};
+use socket::{Flags, Socket};
+
+/// A nanomsg poll descriptor.
+///
+/// The descriptor is owned by the nanomsg socket, so this doesn't close it.
+#[derive(Debug)]
+struct PollFd(RawFd);
+
+impl AsRawFd for PollFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+/// A poll descriptor registered with the tokio reactor.
+///
+/// The descriptor is only valid as long as the socket is open, so this must be dropped before
+/// the socket is closed.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct ReadyFd(AsyncFd<PollFd>);
+
+impl ReadyFd {
+    fn register(sock: &Socket, option: i32) -> Result<ReadyFd> {
+        let fd = unsafe { sock.get_option::<RawFd>(NN_SOL_SOCKET, option)? };
+        let async_fd = unsafe { AsyncFd::register_with_interest(PollFd(fd), Interest::READABLE) };
+        Ok(ReadyFd(async_fd.map_err(io::Error::from)?))
+    }
+}
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// Protocol sockets that can be wrapped in an [`AsyncSocket`](struct.AsyncSocket.html).
+///
+/// This is implemented for all the protocol sockets, and can't be implemented outside of nmsg.
+pub trait AsyncProtocol: SPSocket + private::Sealed {
+    /// `ReadyFd` if the socket can receive, otherwise `()`.
+    #[doc(hidden)]
+    type RecvFd;
+    /// `ReadyFd` if the socket can send, otherwise `()`.
+    #[doc(hidden)]
+    type SendFd;
+
+    #[doc(hidden)]
+    fn register_fds(&self) -> Result<(Self::RecvFd, Self::SendFd)>;
+}
+
+macro_rules! fd_type {
+    (yes) => { ReadyFd };
+    (no) => { () };
+}
+
+macro_rules! register_fd {
+    (yes, $sock:expr, $option:expr) => { ReadyFd::register($sock.socket(), $option)? };
+    (no, $sock:expr, $option:expr) => { () };
+}
+
+macro_rules! impl_async_protocol {
+    ($($name:ident : recv = $recv:ident, send = $send:ident;)*) => {$(
+        impl private::Sealed for $name {}
+
+        impl AsyncProtocol for $name {
+            type RecvFd = fd_type!($recv);
+            type SendFd = fd_type!($send);
+
+            fn register_fds(&self) -> Result<(Self::RecvFd, Self::SendFd)> {
+                Ok((register_fd!($recv, self, NN_RCVFD), register_fd!($send, self, NN_SNDFD)))
+            }
+        }
+    )*}
+}
+
+impl_async_protocol!{
+    Pub: recv = no, send = yes;
+    Sub: recv = yes, send = no;
+    Bus: recv = yes, send = yes;
+    Req: recv = yes, send = yes;
+    Rep: recv = yes, send = yes;
+    Push: recv = no, send = yes;
+    Pull: recv = yes, send = no;
+    Surveyor: recv = yes, send = yes;
+    Respondent: recv = yes, send = yes;
+    Pair: recv = yes, send = yes;
+}
+
+/// An asynchronous wrapper around a protocol socket.
+///
+/// # See Also
+///
+/// * [`AsyncRecv`](trait.AsyncRecv.html)
+/// * [`AsyncSend`](trait.AsyncSend.html)
+pub struct AsyncSocket<S: AsyncProtocol> {
+    // The descriptors are declared first, so that they are deregistered before the socket closes.
+    recv_fd: S::RecvFd,
+    send_fd: S::SendFd,
+    sock: S
+}
+
+impl<S: AsyncProtocol> AsyncSocket<S> {
+    /// Wrap a socket.
+    ///
+    /// This registers the poll descriptors of the socket with the current tokio reactor, so it
+    /// must be called from within a tokio runtime with IO enabled.
+    pub fn new(sock: S) -> Result<AsyncSocket<S>> {
+        let (recv_fd, send_fd) = sock.register_fds()?;
+        Ok(AsyncSocket { recv_fd, send_fd, sock })
+    }
+
+    /// Get a reference to the wrapped socket.
+    ///
+    /// This can be used to bind, connect, or set options on the socket.
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.sock
+    }
+
+    /// Deregister the socket from the reactor, and return it.
+    pub fn into_inner(self) -> S {
+        self.sock
+    }
+}
+
+/// Asynchronous version of [`SPRecv`](../protocol/trait.SPRecv.html).
+pub trait AsyncRecv {
+    /// Attempt to receive a message.
+    ///
+    /// If no message is available, the current task is woken once one may be.
+    fn poll_recv(&self, cx: &mut Context) -> Poll<Result<MessageBuffer>>;
+
+    /// Receive a message.
+    ///
+    /// The returned future resolves once a message has been received.
+    fn recv(&self) -> RecvFuture<'_, Self> where Self: Sized {
+        RecvFuture { sock: self }
+    }
+}
+
+/// Asynchronous version of [`SPSend`](../protocol/trait.SPSend.html).
+pub trait AsyncSend {
+    /// Attempt to send the message in `buffer`.
+    ///
+    /// If the message is sent, `buffer` is emptied and the number of bytes sent is returned. If
+    /// the socket isn't ready, the message is left in `buffer`, and the current task is woken
+    /// once sending may be possible.
+    ///
+    /// # Panics
+    ///
+    /// If `buffer` is `None`.
+    fn poll_send(&self, cx: &mut Context, buffer: &mut Option<MessageBuffer>) -> Poll<Result<usize>>;
+
+    /// Send a message.
+    ///
+    /// The returned future resolves to the number of bytes sent once the message has been sent.
+    fn send(&self, buffer: MessageBuffer) -> SendFuture<'_, Self> where Self: Sized {
+        SendFuture { sock: self, buffer: Some(buffer) }
+    }
+}
+
+impl<S> AsyncRecv for AsyncSocket<S> where S: SPRecv + AsyncProtocol<RecvFd = ReadyFd> {
+    fn poll_recv(&self, cx: &mut Context) -> Poll<Result<MessageBuffer>> {
+        loop {
+            let mut guard = ready!(self.recv_fd.0.poll_read_ready(cx))?;
+            match self.sock.socket().recv(Flags::DONTWAIT) {
+                Err(WOULD_BLOCK) => guard.clear_ready(),
+                res => return Poll::Ready(res)
+            }
+        }
+    }
+}
+
+impl<S> AsyncSend for AsyncSocket<S> where S: SPSend + AsyncProtocol<SendFd = ReadyFd> {
+    fn poll_send(&self, cx: &mut Context, buffer: &mut Option<MessageBuffer>) -> Poll<Result<usize>> {
+        loop {
+            let mut guard = ready!(self.send_fd.0.poll_read_ready(cx))?;
+            let msg = buffer.take().expect("poll_send called without a message");
+            match self.sock.socket().try_send(msg, Flags::DONTWAIT) {
+                Ok(size) => return Poll::Ready(Ok(size)),
+                Err((WOULD_BLOCK, msg)) => {
+                    *buffer = Some(msg);
+                    guard.clear_ready();
+                },
+                Err((e, _)) => return Poll::Ready(Err(e))
+            }
+        }
+    }
+}
+
+/// Future returned by [`AsyncRecv::recv`](trait.AsyncRecv.html#method.recv).
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct RecvFuture<'a, S: 'a> {
+    sock: &'a S
+}
+
+impl<'a, S: AsyncRecv> Future for RecvFuture<'a, S> {
+    type Output = Result<MessageBuffer>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<MessageBuffer>> {
+        self.sock.poll_recv(cx)
+    }
+}
+
+/// Future returned by [`AsyncSend::send`](trait.AsyncSend.html#method.send).
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct SendFuture<'a, S: 'a> {
+    sock: &'a S,
+    buffer: Option<MessageBuffer>
+}
+
+impl<'a, S: AsyncSend> Future for SendFuture<'a, S> {
+    type Output = Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        this.sock.poll_send(cx, &mut this.buffer)
+    }
+}
+
+impl AsyncSocket<Req> {
+    /// Send a request and wait for the reply.
+    ///
+    /// See [`Req::request`](../protocol/struct.Req.html#method.request).
+    pub fn request(&self, body: MessageBuffer) -> RequestFuture<'_> {
+        RequestFuture { sock: self, body: Some(body) }
+    }
+}
+
+/// Future returned by [`AsyncSocket::request`](struct.AsyncSocket.html#method.request).
+#[must_use = "futures do nothing unless polled"]
+pub struct RequestFuture<'a> {
+    sock: &'a AsyncSocket<Req>,
+    body: Option<MessageBuffer>
+}
+
+impl<'a> Future for RequestFuture<'a> {
+    type Output = Result<MessageBuffer>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<MessageBuffer>> {
+        let this = self.get_mut();
+        if this.body.is_some() {
+            ready!(this.sock.poll_send(cx, &mut this.body))?;
+        }
+        this.sock.poll_recv(cx)
+    }
+}
+
+impl AsyncSocket<Surveyor> {
+    /// Send out a survey and collect responses until the survey deadline expires.
+    ///
+    /// See [`Surveyor::survey`](../protocol/struct.Surveyor.html#method.survey).
+    pub fn survey(&self, message: MessageBuffer) -> SurveyFuture<'_> {
+        SurveyFuture {
+            sock: self,
+            message: Some(message),
+            responses: Vec::new(),
+            deadline: None
+        }
+    }
+}
+
+/// Future returned by [`AsyncSocket::survey`](struct.AsyncSocket.html#method.survey).
+#[must_use = "futures do nothing unless polled"]
+pub struct SurveyFuture<'a> {
+    sock: &'a AsyncSocket<Surveyor>,
+    message: Option<MessageBuffer>,
+    responses: Vec<MessageBuffer>,
+    deadline: Option<Pin<Box<Sleep>>>
+}
+
+impl<'a> Future for SurveyFuture<'a> {
+    type Output = Result<Vec<MessageBuffer>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<Vec<MessageBuffer>>> {
+        let this = self.get_mut();
+        if this.message.is_some() {
+            ready!(this.sock.poll_send(cx, &mut this.message))?;
+            // nanomsg reports the end of the survey with TIMED_OUT, the timer is only a safeguard
+            // in case the deadline passes without the socket being signalled.
+            let deadline = this.sock.get_ref().get_survey_deadline();
+            this.deadline = Some(Box::pin(sleep(Duration::from_millis(deadline.max(0) as u64))));
+        }
+        loop {
+            match this.sock.poll_recv(cx) {
+                Poll::Ready(Ok(resp)) => this.responses.push(resp),
+                Poll::Ready(Err(TIMED_OUT)) => break,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => {
+                    let expired = match this.deadline {
+                        Some(ref mut deadline) => deadline.as_mut().poll(cx).is_ready(),
+                        None => false
+                    };
+                    if expired {
+                        break;
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+        let mut responses = mem::take(&mut this.responses);
+        responses.shrink_to_fit();
+        Poll::Ready(Ok(responses))
+    }
+}
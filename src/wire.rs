@@ -0,0 +1,75 @@
+//! Reading the envelopes of the crate's own message formats.
+//!
+//! Integers are big endian, and strings are UTF-8 prefixed with their length as a `u16`.
+use std::str;
+
+use error::{self, INVALID};
+
+/// The envelope is truncated, or a string isn't valid UTF-8.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Malformed;
+
+impl From<Malformed> for error::Error {
+    fn from(_: Malformed) -> error::Error {
+        INVALID
+    }
+}
+
+/// Reads fields from the front of a message.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8]
+}
+
+// Some fields are only read by rpc
+#[cfg_attr(not(feature = "serde"), allow(dead_code))]
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes }
+    }
+
+    /// The bytes that haven't been read yet.
+    #[inline]
+    pub(crate) fn rest(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], Malformed> {
+        if self.bytes.len() < len {
+            return Err(Malformed);
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Malformed> {
+        let mut buf = [0; N];
+        buf.copy_from_slice(self.take(N)?);
+        Ok(buf)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, Malformed> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, Malformed> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, Malformed> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, Malformed> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn i32(&mut self) -> Result<i32, Malformed> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn str(&mut self) -> Result<&'a str, Malformed> {
+        let len = self.u16()? as usize;
+        str::from_utf8(self.take(len)?).map_err(|_| Malformed)
+    }
+}
@@ -0,0 +1,41 @@
+extern crate nmsg;
+
+use std::thread;
+
+use nmsg::{MessageBuffer, Pull, Push, SPRecv, SPSend, SPSocket};
+
+#[test]
+fn send_received_buffer_from_another_thread() {
+    let pull = Pull::new().unwrap();
+    pull.bind("inproc://alloc-forward-in").unwrap();
+    let push = Push::new().unwrap();
+    push.connect("inproc://alloc-forward-in").unwrap();
+    let out_pull = Pull::new().unwrap();
+    out_pull.bind("inproc://alloc-forward-out").unwrap();
+
+    push.send_buf(b"across threads").unwrap();
+    let msg = pull.recv().unwrap();
+    let sender = thread::spawn(move || {
+        let out_push = Push::new().unwrap();
+        out_push.connect("inproc://alloc-forward-out").unwrap();
+        // The chunk received on the main thread is handed to nanomsg on this one
+        out_push.send(msg).unwrap();
+    });
+    sender.join().unwrap();
+    assert_eq!(&out_pull.recv().unwrap()[..], b"across threads");
+}
+
+#[test]
+fn drop_received_buffer_on_another_thread() {
+    let pull = Pull::new().unwrap();
+    pull.bind("inproc://alloc-drop").unwrap();
+    let push = Push::new().unwrap();
+    push.connect("inproc://alloc-drop").unwrap();
+
+    push.send(MessageBuffer::from("dropped elsewhere")).unwrap();
+    let msg = pull.recv().unwrap();
+    thread::spawn(move || {
+        assert_eq!(&msg[..], b"dropped elsewhere");
+        drop(msg);
+    }).join().unwrap();
+}
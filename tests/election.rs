@@ -0,0 +1,157 @@
+extern crate nmsg;
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use nmsg::{Bus, SPSocket};
+use nmsg::election::{ElectionEvent, LeaderElection, Role};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(20);
+const ELECTION_TIMEOUT: Duration = Duration::from_millis(200);
+
+fn node(id: u64, addr: &str) -> LeaderElection {
+    let bus = Bus::new().unwrap();
+    bus.bind(addr).unwrap();
+    let mut node = LeaderElection::new(bus, id);
+    node.set_heartbeat_interval(HEARTBEAT_INTERVAL);
+    node.set_election_timeout(ELECTION_TIMEOUT);
+    node
+}
+
+/// Nodes bound to `addrs`, each connected to all the others.
+fn mesh(addrs: &[&str]) -> Vec<LeaderElection> {
+    let mut nodes = Vec::new();
+    for (i, addr) in addrs.iter().enumerate() {
+        let node = node(i as u64 + 1, addr);
+        for other in &addrs[..i] {
+            node.get_ref().connect(other).unwrap();
+        }
+        nodes.push(node);
+    }
+    nodes
+}
+
+/// A random number below `bound`.
+fn random(bound: u64) -> u64 {
+    RandomState::new().build_hasher().finish() % bound
+}
+
+/// Poll the nodes in turn until `done` returns true for an event, or `limit` passes.
+///
+/// Returns how long it took, or `None` if `limit` passed first.
+fn pump_until<F>(nodes: &mut [LeaderElection], limit: Duration, mut done: F) -> Option<Duration>
+    where F: FnMut(u64, ElectionEvent) -> bool
+{
+    let start = Instant::now();
+    while start.elapsed() < limit {
+        for node in nodes.iter_mut() {
+            for event in node.poll(Some(Duration::from_millis(1))).unwrap() {
+                if done(node.id(), event) {
+                    return Some(start.elapsed());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Poll the nodes for `duration`, returning every event with the id of its node.
+fn pump(nodes: &mut [LeaderElection], duration: Duration) -> Vec<(u64, ElectionEvent)> {
+    let mut events = Vec::new();
+    pump_until(nodes, duration, |id, event| {
+        events.push((id, event));
+        false
+    });
+    events
+}
+
+fn became_leader(event: ElectionEvent) -> bool {
+    matches!(event, ElectionEvent::BecameLeader { .. })
+}
+
+#[test]
+fn follower_takes_over_from_crashed_leader() {
+    let addrs = ["inproc://election-crash-1", "inproc://election-crash-2", "inproc://election-crash-3"];
+    let mut nodes = mesh(&addrs);
+    assert!(pump_until(&mut nodes, 10 * ELECTION_TIMEOUT, |_, event| became_leader(event)).is_some());
+    // Let the followers hear the leader
+    pump(&mut nodes, 5 * HEARTBEAT_INTERVAL);
+
+    let leader = nodes.iter().position(LeaderElection::is_leader).unwrap();
+    drop(nodes.remove(leader));
+    let took = pump_until(&mut nodes, 10 * ELECTION_TIMEOUT, |_, event| became_leader(event))
+        .expect("no follower became leader");
+    // Up to one and a half election timeouts as a follower, and one as a candidate
+    assert!(took < 3 * ELECTION_TIMEOUT, "took {:?}", took);
+}
+
+#[test]
+fn leadership_doesnt_flap_with_jittered_polls() {
+    let addrs = [
+        "inproc://election-jitter-1",
+        "inproc://election-jitter-2",
+        "inproc://election-jitter-3",
+        "inproc://election-jitter-4"
+    ];
+    let mut nodes = mesh(&addrs);
+    // Poll a random node for a random time, so that the nodes see messages and timeouts late and
+    // in no particular order
+    let mut events = Vec::new();
+    let start = Instant::now();
+    while start.elapsed() < 10 * ELECTION_TIMEOUT {
+        let node = &mut nodes[random(addrs.len() as u64) as usize];
+        for event in node.poll(Some(Duration::from_millis(random(5)))).unwrap() {
+            events.push((node.id(), event));
+        }
+    }
+
+    let first = events.iter().position(|&(_, event)| became_leader(event)).expect("no leader elected");
+    let lost = events[first..].iter().any(|&(_, event)| matches!(event, ElectionEvent::LostLeadership { .. }));
+    assert!(!lost, "leadership flapped: {:?}", events);
+}
+
+#[test]
+fn same_term_tie_goes_to_higher_id() {
+    let mut nodes = vec![node(1, "inproc://election-tie-1"), node(2, "inproc://election-tie-2")];
+    // Both nodes claim term 1 before they can hear each other
+    let start = Instant::now();
+    while nodes.iter().any(|n| n.role() != Role::Candidate) {
+        assert!(start.elapsed() < 10 * ELECTION_TIMEOUT);
+        pump(&mut nodes, Duration::from_millis(1));
+    }
+    nodes[1].get_ref().connect("inproc://election-tie-1").unwrap();
+
+    let events = pump(&mut nodes, 3 * ELECTION_TIMEOUT);
+    assert!(events.contains(&(2, ElectionEvent::BecameLeader { term: 1 })));
+    assert!(nodes[1].is_leader());
+    assert_eq!(nodes[0].role(), Role::Follower);
+    assert_eq!(nodes[0].leader(), Some(2));
+    assert_eq!((nodes[0].term(), nodes[1].term()), (1, 1));
+}
+
+#[test]
+fn lower_leader_steps_down_when_split_heals() {
+    let mut nodes = vec![node(1, "inproc://election-heal-1"), node(2, "inproc://election-heal-2")];
+    let link = nodes[1].get_ref().connect("inproc://election-heal-1").unwrap();
+    assert!(pump_until(&mut nodes, 10 * ELECTION_TIMEOUT, |_, event| became_leader(event)).is_some());
+    pump(&mut nodes, 5 * HEARTBEAT_INTERVAL);
+    let old = nodes.iter().position(LeaderElection::is_leader).unwrap();
+    let new = 1 - old;
+    let old_term = nodes[old].term();
+
+    // Split: the follower elects itself in a later term, while the old leader keeps leading
+    link.shutdown().unwrap();
+    let new_id = nodes[new].id();
+    assert!(pump_until(&mut nodes, 10 * ELECTION_TIMEOUT, |id, event| id == new_id && became_leader(event)).is_some());
+    assert!(nodes[old].is_leader());
+    assert!(nodes[new].term() > old_term);
+
+    // Heal: the old leader hears the stronger leader and steps down
+    nodes[1].get_ref().connect("inproc://election-heal-1").unwrap();
+    let old_id = nodes[old].id();
+    let lost = ElectionEvent::LostLeadership { term: old_term };
+    assert!(pump_until(&mut nodes, 10 * ELECTION_TIMEOUT, |id, event| id == old_id && event == lost).is_some());
+    assert!(nodes[new].is_leader());
+    assert_eq!(nodes[old].leader(), Some(new_id));
+}
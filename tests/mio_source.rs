@@ -0,0 +1,51 @@
+extern crate mio;
+extern crate nmsg;
+
+use std::time::Duration;
+
+use mio::{Events, Interest, Poll, Token};
+
+use nmsg::{Pull, Push, SPRecv, SPSend, SPSocket};
+use nmsg::error::WOULD_BLOCK;
+
+const PULL: Token = Token(0);
+
+fn poll_count(poll: &mut Poll, events: &mut Events) -> usize {
+    poll.poll(events, Some(Duration::from_millis(200))).unwrap();
+    events.iter().filter(|e| e.token() == PULL).count()
+}
+
+#[test]
+fn edge_triggered_until_drained() {
+    let mut pull = Pull::new().unwrap();
+    pull.bind("inproc://mio-source-edge").unwrap();
+    let push = Push::new().unwrap();
+    push.connect("inproc://mio-source-edge").unwrap();
+
+    let mut poll = Poll::new().unwrap();
+    let mut events = Events::with_capacity(16);
+    poll.registry().register(&mut pull, PULL, Interest::READABLE).unwrap();
+
+    push.send_buf(b"one").unwrap();
+    push.send_buf(b"two").unwrap();
+    assert_eq!(poll_count(&mut poll, &mut events), 1);
+    // Both messages are still queued, but the socket doesn't become ready again
+    assert_eq!(poll_count(&mut poll, &mut events), 0);
+
+    assert_eq!(&pull.recv_nb().unwrap()[..], b"one");
+    assert_eq!(&pull.recv_nb().unwrap()[..], b"two");
+    assert_eq!(pull.recv_nb().unwrap_err(), WOULD_BLOCK);
+
+    push.send_buf(b"three").unwrap();
+    assert_eq!(poll_count(&mut poll, &mut events), 1);
+    assert_eq!(&pull.recv_nb().unwrap()[..], b"three");
+}
+
+#[test]
+fn unsupported_interest_registers_nothing() {
+    let mut pull = Pull::new().unwrap();
+    let poll = Poll::new().unwrap();
+    assert!(poll.registry().register(&mut pull, PULL, Interest::READABLE | Interest::WRITABLE).is_err());
+    // The receive descriptor wasn't left behind, so registering again succeeds
+    poll.registry().register(&mut pull, PULL, Interest::READABLE).unwrap();
+}
@@ -0,0 +1,17 @@
+extern crate nmsg;
+
+use std::time::Duration;
+
+use nmsg::pipeline::LocalPipeline;
+
+#[test]
+fn run_job_larger_than_socket_buffers() {
+    let mut pipeline = LocalPipeline::new(2, |task: &[u8]| task.to_vec()).unwrap();
+    // Far more than the send and receive buffers of the sockets can hold
+    let tasks = (0..10_000u32).map(|i| vec![(i % 251) as u8; 1024]).collect::<Vec<_>>();
+
+    let results = pipeline.run(&tasks, Some(Duration::from_secs(30))).unwrap();
+    assert!(results.is_complete());
+    assert_eq!(results.len(), tasks.len());
+    assert_eq!(results.get(1234), Some(&tasks[1234][..]));
+}
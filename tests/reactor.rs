@@ -0,0 +1,17 @@
+extern crate nmsg;
+
+use std::cell::Cell;
+use std::time::Duration;
+
+use nmsg::reactor::Reactor;
+
+#[test]
+fn zero_interval_fires_once_per_iteration() {
+    let count = Cell::new(0);
+    let mut reactor = Reactor::new().unwrap();
+    reactor.every(Duration::from_secs(0), || count.set(count.get() + 1));
+
+    reactor.run_once(Some(Duration::from_millis(10))).unwrap();
+    reactor.run_once(Some(Duration::from_millis(10))).unwrap();
+    assert_eq!(count.get(), 2);
+}
@@ -0,0 +1,107 @@
+extern crate nmsg;
+
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nmsg::{Rep, Req, SPRecv, SPSend, SPSocket};
+use nmsg::codec::Json;
+use nmsg::rpc::{self, Client, ErrorCode, RemoteError, Server};
+
+/// Serve `calls` calls on `addr` from another thread.
+fn serve(addr: &'static str, calls: usize) -> JoinHandle<()> {
+    let rep = Rep::new().unwrap();
+    rep.bind(addr).unwrap();
+    thread::spawn(move || {
+        let mut server = Server::new(rep, Json);
+        server.register("add", |(a, b): (i32, i32)| Ok(a + b));
+        server.register("fail", |()| -> Result<(), RemoteError> {
+            Err(RemoteError::application(42, "failed"))
+        });
+        for _ in 0..calls {
+            server.handle().unwrap();
+        }
+    })
+}
+
+fn client(addr: &str) -> Client<Json> {
+    let req = Req::new().unwrap();
+    req.connect(addr).unwrap();
+    Client::new(req, Json)
+}
+
+fn remote_code<T: std::fmt::Debug>(result: rpc::Result<T>) -> ErrorCode {
+    match result {
+        Err(rpc::Error::Remote(err)) => err.code,
+        other => panic!("expected a remote error, got {:?}", other)
+    }
+}
+
+#[test]
+fn call_succeeds() {
+    let server = serve("inproc://rpc-success", 1);
+    let mut client = client("inproc://rpc-success");
+    assert_eq!(client.call::<_, i32>("add", &(2, 3)).unwrap(), 5);
+    server.join().unwrap();
+}
+
+#[test]
+fn unknown_method() {
+    let server = serve("inproc://rpc-unknown", 1);
+    let mut client = client("inproc://rpc-unknown");
+    assert_eq!(remote_code(client.call::<_, i32>("sub", &(2, 3))), ErrorCode::UnknownMethod);
+    server.join().unwrap();
+}
+
+#[test]
+fn bad_arguments() {
+    let server = serve("inproc://rpc-bad-args", 1);
+    let mut client = client("inproc://rpc-bad-args");
+    assert_eq!(remote_code(client.call::<_, i32>("add", "two and three")), ErrorCode::BadRequest);
+    server.join().unwrap();
+}
+
+#[test]
+fn application_error() {
+    let server = serve("inproc://rpc-application", 1);
+    let mut client = client("inproc://rpc-application");
+    assert_eq!(remote_code(client.call::<_, ()>("fail", &())), ErrorCode::Application(42));
+    server.join().unwrap();
+}
+
+#[test]
+fn deadline_exceeded() {
+    let server = serve("inproc://rpc-deadline", 1);
+    let req = Req::new().unwrap();
+    req.connect("inproc://rpc-deadline").unwrap();
+
+    // A client can't send a deadline that has already passed, so build the request by hand
+    let past = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64 - 1000;
+    let mut request = vec![1, 0];
+    request.extend_from_slice(&7u64.to_be_bytes());
+    request.extend_from_slice(&past.to_be_bytes());
+    request.extend_from_slice(&0u64.to_be_bytes());
+    request.extend_from_slice(&3u16.to_be_bytes());
+    request.extend_from_slice(b"add[2,3]");
+    req.send_buf(&request).unwrap();
+
+    let reply = req.recv().unwrap();
+    // [version][kind = error][call id = 7][code = deadline exceeded]
+    assert_eq!(&reply[..2], &[1, 2]);
+    assert_eq!(&reply[2..10], &7u64.to_be_bytes());
+    assert_eq!(reply[10], 3);
+    server.join().unwrap();
+}
+
+#[test]
+fn malformed_request_reply_answers_call() {
+    let rep = Rep::new().unwrap();
+    rep.bind("inproc://rpc-malformed").unwrap();
+    let server = thread::spawn(move || {
+        rep.recv().unwrap();
+        let err = RemoteError::new(ErrorCode::BadRequest, "malformed envelope");
+        rep.send(rpc::encode_reply(0, Err(&err))).unwrap();
+    });
+    let mut client = client("inproc://rpc-malformed");
+    assert_eq!(remote_code(client.call::<_, i32>("add", &(2, 3))), ErrorCode::BadRequest);
+    server.join().unwrap();
+}
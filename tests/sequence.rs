@@ -0,0 +1,51 @@
+extern crate nmsg;
+
+use std::thread;
+use std::time::Duration;
+
+use nmsg::{Pub, SPSocket, Sub};
+use nmsg::sequence::{RecvEvent, SequencedPub, SequencedSub};
+
+fn publisher(addr: &str) -> SequencedPub {
+    let sock = Pub::new().unwrap();
+    sock.bind(addr).unwrap();
+    SequencedPub::new(sock)
+}
+
+/// Give the subscriber time to attach, since a `Pub` drops messages until then.
+fn settle() {
+    thread::sleep(Duration::from_millis(50));
+}
+
+fn message(event: RecvEvent) -> (Vec<u8>, u64) {
+    match event {
+        RecvEvent::Message(msg) => (msg.body().to_vec(), msg.sequence()),
+        other => panic!("expected a message, got {:?}", other)
+    }
+}
+
+#[test]
+fn restarted_publisher_resets() {
+    let sub = Sub::new().unwrap();
+    sub.subscribe(b"");
+    let mut sub = SequencedSub::new(sub);
+
+    let mut publisher_a = publisher("inproc://sequence-restart");
+    sub.get_ref().connect("inproc://sequence-restart").unwrap();
+    settle();
+    publisher_a.send(b"t", b"one").unwrap();
+    publisher_a.send(b"t", b"two").unwrap();
+    assert_eq!(message(sub.recv().unwrap()), (b"one".to_vec(), 1));
+    assert_eq!(message(sub.recv().unwrap()), (b"two".to_vec(), 2));
+    drop(publisher_a);
+
+    // The new publisher starts again at 1, which isn't mistaken for a duplicate
+    let mut publisher_b = publisher("inproc://sequence-restart");
+    settle();
+    publisher_b.send(b"t", b"again").unwrap();
+    match sub.recv().unwrap() {
+        RecvEvent::Reset { topic } => assert_eq!(topic, b"t"),
+        other => panic!("expected a reset, got {:?}", other)
+    }
+    assert_eq!(message(sub.recv().unwrap()), (b"again".to_vec(), 1));
+}
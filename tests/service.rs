@@ -0,0 +1,113 @@
+extern crate nmsg;
+
+use std::thread::{self, JoinHandle};
+
+use nmsg::{Rep, Req, SPSocket};
+use nmsg::codec::Json;
+use nmsg::rpc::{self, ErrorCode, RemoteError};
+use nmsg::service;
+
+#[service]
+pub trait Calculator {
+    fn add(&self, a: i32, b: i32) -> i32;
+    fn divide(&self, a: i32, b: i32) -> Result<i32, DivideByZero>;
+}
+
+pub struct DivideByZero;
+
+impl From<DivideByZero> for RemoteError {
+    fn from(_: DivideByZero) -> RemoteError {
+        RemoteError::application(1, "division by zero")
+    }
+}
+
+struct Calc;
+
+impl Calculator for Calc {
+    fn add(&self, a: i32, b: i32) -> i32 {
+        a + b
+    }
+
+    fn divide(&self, a: i32, b: i32) -> Result<i32, DivideByZero> {
+        a.checked_div(b).ok_or(DivideByZero)
+    }
+}
+
+/// A later version of the service, with a different signature. Only its client is used.
+#[allow(dead_code)]
+mod v2 {
+    use nmsg::service;
+
+    #[service]
+    pub trait Calculator {
+        fn add(&self, a: i64, b: i64) -> i64;
+    }
+}
+
+/// Serve `calls` calls on `addr` from another thread.
+fn serve(addr: &'static str, calls: usize) -> JoinHandle<()> {
+    let rep = Rep::new().unwrap();
+    rep.bind(addr).unwrap();
+    thread::spawn(move || {
+        let dispatcher = CalculatorDispatcher::new(Calc, Json);
+        for _ in 0..calls {
+            rep.reply(|request| dispatcher.handle(request)).unwrap();
+        }
+    })
+}
+
+fn client(addr: &str) -> CalculatorClient<Json> {
+    let req = Req::new().unwrap();
+    req.connect(addr).unwrap();
+    CalculatorClient::new(req, Json)
+}
+
+fn remote_code<T: std::fmt::Debug>(result: rpc::Result<T>) -> ErrorCode {
+    match result {
+        Err(rpc::Error::Remote(err)) => err.code,
+        other => panic!("expected a remote error, got {:?}", other)
+    }
+}
+
+#[test]
+fn round_trip() {
+    let server = serve("inproc://service-round-trip", 1);
+    let mut client = client("inproc://service-round-trip");
+    assert_eq!(client.add(2, 3).unwrap(), 5);
+    server.join().unwrap();
+}
+
+#[test]
+fn result_method() {
+    let server = serve("inproc://service-result", 2);
+    let mut client = client("inproc://service-result");
+    assert_eq!(client.divide(6, 3).unwrap(), 2);
+    assert_eq!(remote_code(client.divide(1, 0)), ErrorCode::Application(1));
+    server.join().unwrap();
+}
+
+#[test]
+fn reply_loop() {
+    let rep = Rep::new().unwrap();
+    rep.bind("inproc://service-reply-loop").unwrap();
+    // The loop only ends with an error, so the thread is left running
+    thread::spawn(move || {
+        let dispatcher = CalculatorDispatcher::new(Calc, Json);
+        rep.reply_loop(&|request| dispatcher.handle(request))
+    });
+    let mut client = client("inproc://service-reply-loop");
+    for i in 0..3 {
+        assert_eq!(client.add(i, i).unwrap(), 2 * i);
+    }
+}
+
+#[test]
+fn version_mismatch() {
+    assert_ne!(CalculatorMethod::SERVICE_VERSION, v2::CalculatorMethod::SERVICE_VERSION);
+    let server = serve("inproc://service-version", 1);
+    let req = Req::new().unwrap();
+    req.connect("inproc://service-version").unwrap();
+    let mut client = v2::CalculatorClient::new(req, Json);
+    assert_eq!(remote_code(client.add(2, 3)), ErrorCode::VersionMismatch);
+    server.join().unwrap();
+}
@@ -0,0 +1,95 @@
+extern crate nmsg;
+extern crate tokio;
+
+use std::thread;
+use std::time::Duration;
+
+use tokio::runtime::{Builder, Runtime};
+
+use nmsg::{MessageBuffer, Pull, Push, Rep, Req, Respondent, SPRecv, SPSend, SPSocket, Surveyor};
+use nmsg::tokio_socket::{AsyncRecv, AsyncSend, AsyncSocket};
+
+/// A current-thread runtime. Peers that don't need to be async run on their own threads with
+/// blocking sockets.
+fn runtime() -> Runtime {
+    Builder::new_current_thread().enable_all().build().unwrap()
+}
+
+#[test]
+fn push_pull() {
+    let rt = runtime();
+    let _guard = rt.enter();
+    let pull = AsyncSocket::new(Pull::new().unwrap()).unwrap();
+    pull.get_ref().bind("inproc://tokio-push-pull").unwrap();
+    let push = AsyncSocket::new(Push::new().unwrap()).unwrap();
+    push.get_ref().connect("inproc://tokio-push-pull").unwrap();
+
+    assert_eq!(rt.block_on(push.send(MessageBuffer::from("hello"))).unwrap(), 5);
+    assert_eq!(&rt.block_on(pull.recv()).unwrap()[..], b"hello");
+}
+
+#[test]
+fn recv_waits_for_send() {
+    let rt = runtime();
+    let _guard = rt.enter();
+    let pull = AsyncSocket::new(Pull::new().unwrap()).unwrap();
+    pull.get_ref().bind("inproc://tokio-recv-waits").unwrap();
+
+    // The receive is polled first, and has to be woken by the send
+    let sender = thread::spawn(|| {
+        let push = Push::new().unwrap();
+        push.connect("inproc://tokio-recv-waits").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        push.send_buf(b"late").unwrap();
+    });
+    assert_eq!(&rt.block_on(pull.recv()).unwrap()[..], b"late");
+    sender.join().unwrap();
+}
+
+#[test]
+fn request() {
+    let rt = runtime();
+    let _guard = rt.enter();
+    let rep = Rep::new().unwrap();
+    rep.bind("inproc://tokio-request").unwrap();
+    let server = thread::spawn(move || {
+        let mut reply = rep.recv().unwrap().to_vec();
+        reply.reverse();
+        rep.send(MessageBuffer::from(reply)).unwrap();
+    });
+
+    let req = AsyncSocket::new(Req::new().unwrap()).unwrap();
+    req.get_ref().connect("inproc://tokio-request").unwrap();
+    assert_eq!(&rt.block_on(req.request(MessageBuffer::from("ping"))).unwrap()[..], b"gnip");
+    server.join().unwrap();
+}
+
+#[test]
+fn survey() {
+    let rt = runtime();
+    let _guard = rt.enter();
+    let surveyor = AsyncSocket::new(Surveyor::new().unwrap()).unwrap();
+    surveyor.get_ref().bind("inproc://tokio-survey").unwrap();
+    surveyor.get_ref().set_survey_deadline(200).unwrap();
+    let respondents = ["a", "b"].iter()
+        .map(|&answer| {
+            let respondent = Respondent::new().unwrap();
+            respondent.connect("inproc://tokio-survey").unwrap();
+            thread::spawn(move || {
+                let question = respondent.recv().unwrap();
+                assert_eq!(&question[..], b"who?");
+                respondent.send(MessageBuffer::from(answer)).unwrap();
+            })
+        })
+        .collect::<Vec<_>>();
+    // Let the respondents connect, since a survey is only sent to connected respondents
+    thread::sleep(Duration::from_millis(50));
+
+    let responses = rt.block_on(surveyor.survey(MessageBuffer::from("who?"))).unwrap();
+    let mut responses = responses.iter().map(|r| r.to_vec()).collect::<Vec<_>>();
+    responses.sort();
+    assert_eq!(responses, vec![b"a".to_vec(), b"b".to_vec()]);
+    for respondent in respondents {
+        respondent.join().unwrap();
+    }
+}